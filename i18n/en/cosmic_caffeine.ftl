@@ -7,16 +7,86 @@ caffeine-mode = Caffeine Mode
 status-off = Caffeine is off
 status-remaining = { $mode } - { $time } remaining
 status-active = { $mode } mode active
+status-clients = { $count ->
+    [one] Kept awake by another application
+   *[other] Kept awake by { $count } applications
+}
 
 # Timer options
 timer-infinity = Infinity
-timer-one-hour = 1 Hour
-timer-two-hours = 2 Hours
-timer-manual = Manual (min)
+timer-hours = { $hours ->
+    [one] 1 Hour
+   *[other] { $hours } Hours
+}
+timer-minutes = { $mins ->
+    [one] 1 Minute
+   *[other] { $mins } Minutes
+}
+timer-manual = Manual
+manual-placeholder = e.g. 1h30m
+timer-until = Until
+timer-morning = Until tomorrow morning ({ $time })
+
+# Inhibit mode
+mode-full = Keep screen on
+mode-allow-lock = Allow screen lock, block suspend
+
+# Inhibit scope
+scope-heading = Prevent
+scope-idle = Screen blanking and lock
+scope-suspend = Suspend
+scope-logout = Logout
+scope-user-switch = User switching
 
 # Buttons
 start-caffeine = Start Caffeine
 stop-caffeine = Stop Caffeine
+extend-15m = +15m
+extend-1h = +1h
+shorten-15m = -15m
+
+# Settings window
+settings = Settings…
+settings-presets = Timer presets
+settings-add-preset = Add a preset
+settings-preset-name = Name
+settings-manual-minutes = Manual timer starts at
+settings-minutes = Minutes
+settings-morning-time = Tomorrow morning means
+settings-add = Add
+settings-default-timer = Default timer
+settings-notifications = Notifications
+settings-notify-all = Show all notifications
+settings-notify-important = Only when caffeine stops on its own
+settings-notify-errors = Only errors
+settings-scope = Inhibit scope
+settings-triggers = Automatic triggers
+settings-trigger-processes = Trigger processes
+settings-trigger-processes-description = Caffeine keeps the session awake while any of these programs runs.
+settings-add-process = Add a process
+settings-process-name = Process name
+settings-restore = Timers and restarts
+settings-restore-infinity = Restore infinity mode at login
+settings-suspend-policy = While suspended
+settings-suspend-count = Keep counting down
+settings-suspend-pause = Pause the timer
+settings-backend = Backend
+settings-backend-restart = Backend changes apply after the panel restarts.
+settings-logind = Logind sleep lock
+settings-logind-mode = Lock type
+settings-logind-block = Block suspend
+settings-logind-delay = Delay suspend
+settings-logind-lid-switch = Also block the lid switch
+
+# Durations typed by the user. The unit lists are accepted in addition to
+# the English h/m/s, hour(s), minute(s), etc.
+duration-units-hours = h, hr, hrs, hour, hours
+duration-units-minutes = m, min, mins, minute, minutes
+duration-units-seconds = s, sec, secs, second, seconds
+duration-error-empty = Enter a duration
+duration-error-invalid = Not a duration, try 90, 1h30m or 1:30
+duration-error-zero = The duration must be longer than zero
+duration-error-too-long = The duration is too long
 
 # Time formatting
 time-hours-minutes = { $hours }h { $mins }m
@@ -30,8 +100,12 @@ notification-disabled-title = Caffeine Mode Deactivated
 notification-disabled-body = Normal power settings restored.
 notification-timer-expired-title = Caffeine Timer Expired
 notification-timer-expired-body = Normal power settings have been restored.
+notification-process-exited-title = Watched Process Exited
+notification-process-exited-body = { $process } has exited. Normal power settings have been restored.
 notification-error-title = Caffeine Error
 notification-error-body = Could not toggle Caffeine Mode.
+notification-inhibit-lost-title = Caffeine Interrupted
+notification-inhibit-lost-body = The inhibit service restarted and the inhibit could not be restored, your screen may lock or the system may sleep.
 
 # Environment warning
 env-warning = This applet is designed specifically for the COSMIC Desktop Environment.
@@ -1,4 +1,6 @@
+use cosmic::cosmic_config;
 use cosmic::iced::futures::{stream, StreamExt};
+use cosmic::iced::mouse::ScrollDelta;
 use cosmic::iced::{window::Id, Color, Length, Rectangle, Subscription};
 use cosmic::prelude::*;
 use cosmic::surface::action::{app_popup, destroy_popup};
@@ -7,16 +9,19 @@ use cosmic::widget;
 use cosmic::widget::MouseArea;
 use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::{Arc, LazyLock, Mutex};
+use std::sync::LazyLock;
 use std::time::Duration;
 use tracing::{error, info, warn};
 
-use crate::backend::CaffeineBackend;
+use crate::clock;
+use crate::config::{self, Config, DefaultTimer};
+use crate::duration::{self, DurationError};
 use crate::fl;
-use crate::service::{CaffeineManagerProxy, CaffeineService, DBUS_NAME, DBUS_PATH};
-use crate::state::{CaffeineState, TimerSelection};
-
-const ACTIVE_COLOR: Color = Color::from_rgb(0.698, 0.133, 0.133);
+use crate::localtime::{self, TimeOfDay};
+use crate::notify;
+use crate::service::{self, CaffeineManagerProxy, StateChanged, DBUS_NAME, DBUS_PATH};
+use crate::settings::{self, SettingsWindow};
+use crate::state::{format_duration, CaffeineState, InhibitMode, InhibitScope, TimerSelection};
 
 const SYSTEM_ICON_PATH: &str =
     "/usr/share/icons/hicolor/scalable/apps/oussama-berchi-caffeine-cosmic.svg";
@@ -38,23 +43,74 @@ fn get_icon_path() -> PathBuf {
 static ICON_HANDLE: LazyLock<widget::icon::Handle> =
     LazyLock::new(|| widget::icon::from_path(get_icon_path()).symbolic(true));
 
+/// The hours and minutes offered by the popup's time picker.
+static HOURS: LazyLock<Vec<String>> =
+    LazyLock::new(|| (0..24).map(|h| format!("{:02}", h)).collect());
+static MINUTES: LazyLock<Vec<String>> =
+    LazyLock::new(|| (0..60).step_by(5).map(|m| format!("{:02}", m)).collect());
+
+/// How far each notch of the scroll wheel over the icon moves the timer.
+const SCROLL_STEP: i64 = 15 * 60;
+
+/// How many pixels of smooth scrolling count as one notch.
+const SCROLL_PIXELS_PER_STEP: f32 = 40.0;
+
+fn active_style(color: Color) -> cosmic::theme::Svg {
+    cosmic::theme::Svg::Custom(Rc::new(move |_theme| cosmic::iced_widget::svg::Style {
+        color: Some(color),
+    }))
+}
+
+/// The timer picked in the popup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimerChoice {
+    #[default]
+    Infinity,
+    /// The preset at this index of `Config::presets`.
+    Preset(usize),
+    Manual,
+    /// Until the time picked in the popup.
+    Until,
+    /// Until `Config::morning_time` tomorrow.
+    Morning,
+}
+
 pub struct AppModel {
     core: cosmic::Core,
-    selected_timer: TimerSelection,
+    config: Config,
+    config_handler: Option<cosmic_config::Config>,
+    settings: Option<SettingsWindow>,
+    selected_timer: TimerChoice,
     manual_input: String,
+    until_time: TimeOfDay,
     caffeine_state: CaffeineState,
+    /// Inhibits held by other applications, from the `Clients` property.
+    clients: u32,
+    /// The running session, from the `SelectionName` property.
+    selection: TimerSelection,
+    scope: InhibitScope,
     popup: Option<Id>,
     proxy: Option<CaffeineManagerProxy<'static>>,
     active_icon_style: cosmic::theme::Svg,
     is_hovered: bool,
+    /// Scrolling over the icon not yet turned into whole steps.
+    scroll_delta: f32,
+    /// Seconds left on the timer, from the `RemainingSeconds` property.
+    remaining_secs: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    SelectTimer(TimerSelection),
+    SelectTimer(TimerChoice),
     ManualInputChanged(String),
+    UntilHour(usize),
+    UntilMinute(usize),
     ToggleCaffeine,
     SetState(bool),
+    SetScope(InhibitScope),
+    /// Move the end of the running timer by this many seconds.
+    Extend(i64),
+    Scrolled(ScrollDelta),
     TimerTick,
     PopupClosed(Id),
     TogglePopup(Rectangle),
@@ -62,6 +118,13 @@ pub enum Message {
     Hover(bool),
     DBusReady(Option<CaffeineManagerProxy<'static>>),
     StateChanged(CaffeineState),
+    RemainingRead(Option<u64>),
+    ClientsChanged(u32),
+    SelectionChanged(String),
+    ScopeChanged(InhibitScope),
+    ConfigChanged(Config),
+    OpenSettings,
+    Settings(settings::Change),
 }
 
 impl cosmic::Application for AppModel {
@@ -69,7 +132,7 @@ impl cosmic::Application for AppModel {
     type Flags = ();
     type Message = Message;
 
-    const APP_ID: &'static str = "com.github.cosmic-caffeine";
+    const APP_ID: &'static str = config::APP_ID;
 
     fn core(&self) -> &cosmic::Core {
         &self.core
@@ -85,20 +148,28 @@ impl cosmic::Application for AppModel {
     ) -> (Self, Task<cosmic::Action<Self::Message>>) {
         info!("Caffeine applet initialized");
 
-        let active_style =
-            cosmic::theme::Svg::Custom(Rc::new(|_theme| cosmic::iced_widget::svg::Style {
-                color: Some(ACTIVE_COLOR),
-            }));
+        let config = Config::load();
+        notify::set_policy(config.notifications);
+        let (backend, session) = (config.backend_config(), config.session_config());
 
         let app = AppModel {
             core,
-            selected_timer: TimerSelection::default(),
-            manual_input: "30".to_string(),
+            selected_timer: default_choice(&config),
+            manual_input: config.manual_minutes.to_string(),
+            until_time: default_until_time(),
             caffeine_state: CaffeineState::inactive(),
+            clients: 0,
+            selection: TimerSelection::default(),
+            scope: InhibitScope::default(),
             popup: None,
             proxy: None,
-            active_icon_style: active_style,
+            active_icon_style: active_style(config.active_color()),
             is_hovered: false,
+            scroll_delta: 0.0,
+            remaining_secs: None,
+            config,
+            config_handler: Config::handler(),
+            settings: None,
         };
 
         let dbus_task = Task::perform(
@@ -111,19 +182,14 @@ impl cosmic::Application for AppModel {
                     }
                 };
 
-                match conn.request_name(DBUS_NAME).await {
-                    Ok(_) => {
-                        info!("Acquired D-Bus name: {}", DBUS_NAME);
-                        let backend = CaffeineBackend::new();
-                        let state = Arc::new(Mutex::new(CaffeineState::inactive()));
-                        let service = CaffeineService::new(backend, state);
-                        if let Err(e) = conn.object_server().at(DBUS_PATH, service).await {
-                            error!("Failed to serve object: {}", e);
-                        }
-                    }
-                    Err(_) => {
+                match service::serve(&conn, backend, session).await {
+                    Ok(true) => {}
+                    Ok(false) => {
                         info!("D-Bus name already taken, acting as client");
                     }
+                    Err(e) => {
+                        error!("Failed to serve object: {}", e);
+                    }
                 }
 
                 match CaffeineManagerProxy::builder(&conn)
@@ -152,8 +218,15 @@ impl cosmic::Application for AppModel {
         Some(Message::PopupClosed(id))
     }
 
+    fn view_window(&self, id: Id) -> Element<'_, Self::Message> {
+        match &self.settings {
+            Some(window) if window.id == id => window.view(&self.config).map(Message::Settings),
+            _ => widget::text::body("").into(),
+        }
+    }
+
     fn view(&self) -> Element<'_, Self::Message> {
-        let is_active = self.caffeine_state.is_active();
+        let is_active = self.caffeine_state.is_active() || self.clients > 0;
 
         let icon_handle = ICON_HANDLE.clone();
 
@@ -209,6 +282,7 @@ impl cosmic::Application for AppModel {
         MouseArea::new(button)
             .on_enter(Message::Hover(true))
             .on_exit(Message::Hover(false))
+            .on_scroll(Message::Scrolled)
             .into()
     }
 
@@ -239,9 +313,21 @@ impl cosmic::Application for AppModel {
             }
 
             Message::ManualInputChanged(value) => {
-                if value.chars().all(|c| c.is_ascii_digit()) {
-                    self.manual_input = value;
+                self.manual_input = value;
+            }
+
+            Message::UntilHour(index) => {
+                if let Some(time) = TimeOfDay::new(index as u8, self.until_time.minute) {
+                    self.until_time = time;
+                }
+                self.selected_timer = TimerChoice::Until;
+            }
+
+            Message::UntilMinute(index) => {
+                if let Some(time) = TimeOfDay::new(self.until_time.hour, index as u8 * 5) {
+                    self.until_time = time;
                 }
+                self.selected_timer = TimerChoice::Until;
             }
 
             Message::ToggleCaffeine => {
@@ -252,21 +338,43 @@ impl cosmic::Application for AppModel {
             Message::SetState(active) => {
                 if let Some(proxy) = &self.proxy {
                     let proxy = proxy.clone();
-                    let selection = self.selected_timer;
-                    let manual_input = self.manual_input.clone();
+                    let until = match self.selected_timer {
+                        TimerChoice::Until => Some(self.until_time),
+                        TimerChoice::Morning => Some(self.config.morning_time()),
+                        _ => None,
+                    };
+                    let secs = match self.selected_timer {
+                        _ if !active => None,
+                        TimerChoice::Infinity | TimerChoice::Until | TimerChoice::Morning => None,
+                        TimerChoice::Preset(index) => match self.config.presets.get(index) {
+                            Some(preset) => Some(preset.secs()),
+                            None => {
+                                warn!("Not starting, preset {} no longer exists", index);
+                                return Task::none();
+                            }
+                        },
+                        TimerChoice::Manual => match self.manual_duration() {
+                            Ok(secs) => Some(secs),
+                            Err(e) => {
+                                warn!("Not starting, manual duration is invalid: {}", e);
+                                return Task::none();
+                            }
+                        },
+                    };
 
                     return Task::perform(
                         async move {
-                            let (idx, mins) = match selection {
-                                TimerSelection::Infinity => (0, 0),
-                                TimerSelection::OneHour => (1, 0),
-                                TimerSelection::TwoHours => (2, 0),
-                                TimerSelection::Manual => {
-                                    (3, manual_input.parse::<u32>().unwrap_or(30))
-                                }
+                            let result = if !active {
+                                proxy.disable().await
+                            } else if let Some(time) = until {
+                                proxy.enable_until_time(&time.to_string()).await
+                            } else if let Some(secs) = secs {
+                                proxy.enable_for(secs).await
+                            } else {
+                                proxy.enable_indefinitely().await
                             };
 
-                            if let Err(e) = proxy.set_state(active, idx, mins).await {
+                            if let Err(e) = result {
                                 error!("Failed to set state via D-Bus: {}", e);
                             }
                             Message::Hover(false)
@@ -278,25 +386,126 @@ impl cosmic::Application for AppModel {
                 }
             }
 
+            Message::Extend(secs) => {
+                if let Some(proxy) = &self.proxy {
+                    let proxy = proxy.clone();
+                    return Task::perform(
+                        async move {
+                            if let Err(e) = proxy.extend(secs).await {
+                                error!("Failed to extend timer via D-Bus: {}", e);
+                            }
+                            Message::Hover(false)
+                        },
+                        cosmic::Action::App,
+                    );
+                } else {
+                    warn!("Proxy not ready, cannot extend timer");
+                }
+            }
+
+            Message::Scrolled(delta) => {
+                self.scroll_delta += match delta {
+                    ScrollDelta::Lines { y, .. } => y,
+                    ScrollDelta::Pixels { y, .. } => y / SCROLL_PIXELS_PER_STEP,
+                };
+                let steps = self.scroll_delta.trunc();
+                self.scroll_delta -= steps;
+
+                // Scrolling up adds time, scrolling down takes it away.
+                let secs = steps as i64 * SCROLL_STEP;
+                if secs != 0 && self.can_extend_by(secs) {
+                    return Task::done(cosmic::Action::App(Message::Extend(secs)));
+                }
+            }
+
+            Message::SetScope(scope) => {
+                if let Some(proxy) = &self.proxy {
+                    let proxy = proxy.clone();
+                    return Task::perform(
+                        async move {
+                            if let Err(e) = proxy.set_scope(scope).await {
+                                error!("Failed to set scope via D-Bus: {}", e);
+                            }
+                            Message::Hover(false)
+                        },
+                        cosmic::Action::App,
+                    );
+                } else {
+                    warn!("Proxy not ready, cannot change scope");
+                }
+            }
+
             Message::StateChanged(new_state) => {
                 info!("State synced from D-Bus: {:?}", new_state);
                 self.caffeine_state = new_state;
+                if !new_state.is_active() {
+                    self.remaining_secs = None;
+                }
+                return self.read_remaining();
             }
 
-            Message::TimerTick => {
-                if let Some(remaining) = self.caffeine_state.remaining_secs() {
-                    if remaining == 0 && self.caffeine_state.is_active() {
-                        info!("Timer expired, disabling caffeine");
-                        crate::notify::notify_timer_expired();
-                        return Task::done(cosmic::Action::App(Message::SetState(false)));
+            Message::RemainingRead(secs) => {
+                self.remaining_secs = secs;
+            }
+
+            Message::ClientsChanged(clients) => {
+                self.clients = clients;
+            }
+
+            Message::SelectionChanged(name) => match TimerSelection::from_name(&name) {
+                Some(selection) => self.selection = selection,
+                None => warn!("Unknown selection {}", name),
+            },
+
+            Message::ScopeChanged(scope) => {
+                self.scope = scope;
+            }
+
+            Message::ConfigChanged(config) => {
+                info!("Config changed: {:?}", config);
+                return self.apply_config(config);
+            }
+
+            Message::OpenSettings => {
+                if let Some(window) = &self.settings {
+                    return cosmic::iced::window::gain_focus(window.id);
+                }
+                let (id, open) = cosmic::iced::window::open(cosmic::iced::window::Settings {
+                    size: cosmic::iced::Size::new(720.0, 520.0),
+                    min_size: Some(cosmic::iced::Size::new(480.0, 360.0)),
+                    ..Default::default()
+                });
+                self.settings = Some(SettingsWindow::new(id, &self.config));
+                let close_popup = match self.popup.take() {
+                    Some(popup) => {
+                        Task::done(cosmic::Action::App(Message::Surface(destroy_popup(popup))))
                     }
+                    None => Task::none(),
+                };
+                return Task::batch([close_popup, open.discard()]);
+            }
+
+            Message::Settings(change) => {
+                if let Some(window) = &mut self.settings {
+                    let mut config = self.config.clone();
+                    window.update(&mut config, self.config_handler.as_ref(), change);
+                    return self.apply_config(config);
                 }
             }
 
+            Message::TimerTick => {
+                // Expiry is handled by the service; the tick only refreshes
+                // the remaining time shown in the popup.
+                return self.read_remaining();
+            }
+
             Message::PopupClosed(id) => {
                 if self.popup.as_ref() == Some(&id) {
                     self.popup = None;
                 }
+                if self.settings.as_ref().is_some_and(|window| window.id == id) {
+                    self.settings = None;
+                }
             }
 
             Message::TogglePopup(anchor_rect) => {
@@ -361,7 +570,7 @@ impl cosmic::Application for AppModel {
                 "dbus-signals",
                 stream::once(async move {
                     info!("Subscribing to state signals...");
-                    match proxy.inner().receive_signal("StateChanged").await {
+                    match proxy.receive_state_changed().await {
                         Ok(stream) => {
                             info!("Successfully subscribed to state signals");
                             stream.boxed()
@@ -373,11 +582,11 @@ impl cosmic::Application for AppModel {
                     }
                 })
                 .flatten()
-                .filter_map(|change: zbus::Message| async move {
-                    match change.body().deserialize::<CaffeineState>() {
-                        Ok(state) => {
-                            info!("Received signal: {:?}", state);
-                            Some(Message::StateChanged(state))
+                .filter_map(|change: StateChanged| async move {
+                    match change.args() {
+                        Ok(args) => {
+                            info!("Received signal: {:?}", args.state);
+                            Some(Message::StateChanged(args.state))
                         }
                         Err(e) => {
                             error!("Failed to parse signal body: {}", e);
@@ -390,7 +599,55 @@ impl cosmic::Application for AppModel {
             Subscription::none()
         };
 
-        Subscription::batch(vec![timer, dbus_signals])
+        // Each stream starts with the property's current value.
+        let dbus_properties = if let Some(proxy) = &self.proxy {
+            let proxy = proxy.clone();
+            Subscription::run_with_id(
+                "dbus-properties",
+                stream::once(async move {
+                    let clients = proxy
+                        .receive_clients_changed()
+                        .await
+                        .then(
+                            |change| async move { change.get().await.map(Message::ClientsChanged) },
+                        );
+                    let selection =
+                        proxy
+                            .receive_selection_name_changed()
+                            .await
+                            .then(|change| async move {
+                                change.get().await.map(Message::SelectionChanged)
+                            });
+                    let scope = proxy
+                        .receive_scope_changed()
+                        .await
+                        .then(
+                            |change| async move { change.get().await.map(Message::ScopeChanged) },
+                        );
+                    stream::select_all([clients.boxed(), selection.boxed(), scope.boxed()])
+                })
+                .flatten()
+                .filter_map(|message| async move {
+                    message
+                        .inspect_err(|e| error!("Failed to read property: {}", e))
+                        .ok()
+                }),
+            )
+        } else {
+            Subscription::none()
+        };
+
+        let config = self
+            .core
+            .watch_config::<Config>(Self::APP_ID)
+            .map(|update| {
+                for e in update.errors {
+                    warn!("Config error: {}", e);
+                }
+                Message::ConfigChanged(update.config)
+            });
+
+        Subscription::batch(vec![timer, dbus_signals, dbus_properties, config])
     }
 
     fn style(&self) -> Option<cosmic::iced_runtime::Appearance> {
@@ -398,6 +655,158 @@ impl cosmic::Application for AppModel {
     }
 }
 
+impl AppModel {
+    /// The manual field's duration in seconds.
+    fn manual_duration(&self) -> Result<u64, DurationError> {
+        duration::parse(&self.manual_input)
+    }
+
+    /// Whether the running timer can be moved by `secs`; shortening it has
+    /// to leave some time on it.
+    fn can_extend_by(&self, secs: i64) -> bool {
+        match self.remaining_secs {
+            Some(remaining) => secs > 0 || remaining > secs.unsigned_abs(),
+            None => false,
+        }
+    }
+
+    /// Ask the service how long is left on the timer.
+    fn read_remaining(&self) -> Task<cosmic::Action<Message>> {
+        let Some(proxy) = self.proxy.clone() else {
+            return Task::none();
+        };
+        Task::perform(
+            async move {
+                match proxy.remaining_seconds().await {
+                    Ok(secs) => Message::RemainingRead(u64::try_from(secs).ok()),
+                    Err(e) => {
+                        error!("Failed to read remaining time: {}", e);
+                        Message::Hover(false)
+                    }
+                }
+            },
+            cosmic::Action::App,
+        )
+    }
+
+    /// Switch to `config`, whether it came from the settings window or was
+    /// changed on disk, and apply what changed.
+    fn apply_config(&mut self, config: Config) -> Task<cosmic::Action<Message>> {
+        notify::set_policy(config.notifications);
+        self.active_icon_style = active_style(config.active_color());
+        if self.manual_input == self.config.manual_minutes.to_string() {
+            self.manual_input = config.manual_minutes.to_string();
+        }
+        // Follow the picked preset by name when presets are removed or
+        // reordered, falling back to the default timer once it is gone.
+        if let TimerChoice::Preset(index) = self.selected_timer {
+            let name = self.config.presets.get(index).map(|preset| &preset.name);
+            self.selected_timer = config
+                .presets
+                .iter()
+                .position(|preset| Some(&preset.name) == name)
+                .map_or_else(|| default_choice(&config), TimerChoice::Preset);
+        }
+        let scope_changed = config.default_scope != self.config.default_scope;
+        let presets_changed = config.presets != self.config.presets;
+        let triggers_changed = config.trigger_processes != self.config.trigger_processes;
+        let default_changed = config.default_timer != self.config.default_timer;
+        self.config = config;
+        if default_changed {
+            self.selected_timer = default_choice(&self.config);
+        }
+
+        let mut tasks = Vec::new();
+        if let Some(proxy) = self.proxy.as_ref().filter(|_| scope_changed) {
+            let conn = proxy.inner().connection().clone();
+            let scope = self.config.default_scope;
+            tasks.push(Task::perform(
+                async move {
+                    if let Err(e) = service::set_scope(&conn, scope).await {
+                        error!("Failed to update scope: {}", e);
+                    }
+                    Message::Hover(false)
+                },
+                cosmic::Action::App,
+            ));
+        }
+        if let Some(proxy) = self.proxy.as_ref().filter(|_| presets_changed) {
+            let conn = proxy.inner().connection().clone();
+            let presets = self.config.presets.clone();
+            tasks.push(Task::perform(
+                async move {
+                    if let Err(e) = service::set_presets(&conn, presets).await {
+                        error!("Failed to update presets: {}", e);
+                    }
+                    Message::Hover(false)
+                },
+                cosmic::Action::App,
+            ));
+        }
+        if let Some(proxy) = self.proxy.as_ref().filter(|_| triggers_changed) {
+            let conn = proxy.inner().connection().clone();
+            let triggers = self.config.trigger_processes.clone();
+            tasks.push(Task::perform(
+                async move {
+                    if let Err(e) = service::set_triggers(&conn, triggers).await {
+                        error!("Failed to update trigger processes: {}", e);
+                    }
+                    Message::Hover(false)
+                },
+                cosmic::Action::App,
+            ));
+        }
+        Task::batch(tasks)
+    }
+}
+
+/// The popup choice `Config::default_timer` names, among the current presets.
+fn default_choice(config: &Config) -> TimerChoice {
+    match &config.default_timer {
+        DefaultTimer::Infinity => TimerChoice::Infinity,
+        DefaultTimer::Preset(name) => config
+            .presets
+            .iter()
+            .position(|preset| &preset.name == name)
+            .map_or(TimerChoice::Infinity, TimerChoice::Preset),
+        DefaultTimer::Manual => TimerChoice::Manual,
+        DefaultTimer::Until => TimerChoice::Until,
+        DefaultTimer::Morning => TimerChoice::Morning,
+    }
+}
+
+/// Where the popup's time picker starts: an hour from now, on a multiple of
+/// five minutes.
+fn default_until_time() -> TimeOfDay {
+    let later = clock::unix_now() + 3600;
+    localtime::time_of_day(later)
+        .and_then(|time| TimeOfDay::new(time.hour, time.minute / 5 * 5))
+        .unwrap_or(TimeOfDay {
+            hour: 17,
+            minute: 0,
+        })
+}
+
+/// How the running `selection` is named in the status line, using the name
+/// of a preset of the same length if there is one.
+fn selection_label(config: &Config, selection: TimerSelection) -> String {
+    selection.label_among(
+        config
+            .presets
+            .iter()
+            .map(|preset| (preset.name.as_str(), preset.secs())),
+    )
+}
+
+/// How a timer of `mins` minutes is written out, e.g. `2 Hours`.
+pub fn preset_label(mins: u32) -> String {
+    match (mins / 60, mins % 60) {
+        (0, mins) => fl!("timer-minutes", mins = mins),
+        (hours, 0) => fl!("timer-hours", hours = hours),
+        (hours, mins) => fl!("time-hours-minutes", hours = hours, mins = mins),
+    }
+}
+
 fn build_popup_content(state: &AppModel) -> Element<'_, Message> {
     let spacing = theme::active().cosmic().spacing;
     let is_active = state.caffeine_state.is_active();
@@ -405,66 +814,53 @@ fn build_popup_content(state: &AppModel) -> Element<'_, Message> {
     let header = widget::text::heading(fl!("caffeine-mode"));
 
     let status_text = if !state.caffeine_state.is_active() {
-        fl!("status-off")
+        match state.clients {
+            0 => fl!("status-off"),
+            count => fl!("status-clients", count = count),
+        }
     } else {
-        let selection = state.caffeine_state.selection;
-        if let Some(secs) = state.caffeine_state.remaining_secs() {
-            let hours = secs / 3600;
-            let mins = (secs % 3600) / 60;
-            let time_str = if hours > 0 {
-                format!("{}h {}m", hours, mins)
-            } else if mins > 0 {
-                format!("{}m", mins)
-            } else {
-                format!("{}s", secs)
-            };
-            format!("{} - {} remaining", selection.label(), time_str)
+        let label = selection_label(&state.config, state.selection);
+        if let Some(secs) = state.remaining_secs {
+            format!("{} - {} remaining", label, format_duration(secs))
         } else {
-            format!("{} mode active", selection.label())
+            format!("{} mode active", label)
         }
     };
 
     let status_indicator = widget::text::caption(status_text);
 
-    let mut options = widget::column()
-        .push(
-            widget::radio(
-                widget::text::body(fl!("timer-infinity")),
-                TimerSelection::Infinity,
-                Some(state.selected_timer),
-                Message::SelectTimer,
-            )
-            .width(Length::Fill),
-        )
-        .push(
-            widget::radio(
-                widget::text::body(fl!("timer-one-hour")),
-                TimerSelection::OneHour,
-                Some(state.selected_timer),
-                Message::SelectTimer,
-            )
-            .width(Length::Fill),
+    let mut options = widget::column().push(
+        widget::radio(
+            widget::text::body(fl!("timer-infinity")),
+            TimerChoice::Infinity,
+            Some(state.selected_timer),
+            Message::SelectTimer,
         )
-        .push(
+        .width(Length::Fill),
+    );
+    for (index, preset) in state.config.presets.iter().enumerate() {
+        options = options.push(
             widget::radio(
-                widget::text::body(fl!("timer-two-hours")),
-                TimerSelection::TwoHours,
+                widget::text::body(&preset.name),
+                TimerChoice::Preset(index),
                 Some(state.selected_timer),
                 Message::SelectTimer,
             )
             .width(Length::Fill),
         );
+    }
 
     let manual_radio = widget::radio(
         widget::text::body(fl!("timer-manual")),
-        TimerSelection::Manual,
+        TimerChoice::Manual,
         Some(state.selected_timer),
         Message::SelectTimer,
     );
 
-    let manual_input = widget::text_input("Mins", &state.manual_input)
+    let manual_error = state.manual_duration().err();
+    let manual_input = widget::text_input(fl!("manual-placeholder"), &state.manual_input)
         .on_input(Message::ManualInputChanged)
-        .width(Length::Fixed(80.0));
+        .width(Length::Fixed(120.0));
 
     let manual_row = widget::row()
         .push(manual_radio)
@@ -472,7 +868,110 @@ fn build_popup_content(state: &AppModel) -> Element<'_, Message> {
         .spacing(spacing.space_xs)
         .align_y(cosmic::iced::Alignment::Center);
 
-    options = options.push(manual_row).spacing(spacing.space_xxs);
+    options = options.push(manual_row);
+    if let Some(e) = &manual_error {
+        options = options.push(widget::text::caption(e.to_string()));
+    }
+
+    let until_radio = widget::radio(
+        widget::text::body(fl!("timer-until")),
+        TimerChoice::Until,
+        Some(state.selected_timer),
+        Message::SelectTimer,
+    );
+    let until_row = widget::row()
+        .push(until_radio)
+        .push(widget::dropdown(
+            HOURS.as_slice(),
+            Some(state.until_time.hour.into()),
+            Message::UntilHour,
+        ))
+        .push(widget::text::body(":"))
+        .push(widget::dropdown(
+            MINUTES.as_slice(),
+            Some(usize::from(state.until_time.minute / 5)),
+            Message::UntilMinute,
+        ))
+        .spacing(spacing.space_xs)
+        .align_y(cosmic::iced::Alignment::Center);
+
+    options = options
+        .push(until_row)
+        .push(
+            widget::radio(
+                widget::text::body(fl!(
+                    "timer-morning",
+                    time = state.config.morning_time().to_string()
+                )),
+                TimerChoice::Morning,
+                Some(state.selected_timer),
+                Message::SelectTimer,
+            )
+            .width(Length::Fill),
+        )
+        .spacing(spacing.space_xxs);
+    let can_start = state.selected_timer != TimerChoice::Manual || manual_error.is_none();
+
+    let scope = state.scope;
+    let mode_options = widget::column()
+        .push(
+            widget::radio(
+                widget::text::body(fl!("mode-full")),
+                InhibitMode::Full,
+                Some(scope.mode()),
+                move |mode| Message::SetScope(scope.with_mode(mode)),
+            )
+            .width(Length::Fill),
+        )
+        .push(
+            widget::radio(
+                widget::text::body(fl!("mode-allow-lock")),
+                InhibitMode::AllowLock,
+                Some(scope.mode()),
+                move |mode| Message::SetScope(scope.with_mode(mode)),
+            )
+            .width(Length::Fill),
+        )
+        .spacing(spacing.space_xxs);
+
+    let scope_options = widget::column()
+        .push(widget::text::body(fl!("scope-heading")))
+        .push(
+            widget::checkbox(fl!("scope-idle"), scope.idle)
+                .on_toggle(move |idle| Message::SetScope(InhibitScope { idle, ..scope })),
+        )
+        .push(
+            widget::checkbox(fl!("scope-suspend"), scope.suspend)
+                .on_toggle(move |suspend| Message::SetScope(InhibitScope { suspend, ..scope })),
+        )
+        .push(
+            widget::checkbox(fl!("scope-logout"), scope.logout)
+                .on_toggle(move |logout| Message::SetScope(InhibitScope { logout, ..scope })),
+        )
+        .push(
+            widget::checkbox(fl!("scope-user-switch"), scope.user_switch).on_toggle(
+                move |user_switch| {
+                    Message::SetScope(InhibitScope {
+                        user_switch,
+                        ..scope
+                    })
+                },
+            ),
+        )
+        .spacing(spacing.space_xxs);
+
+    let extend_buttons = state.remaining_secs.map(|_| {
+        let button = |label: String, secs: i64| {
+            widget::button::standard(label)
+                .on_press_maybe(state.can_extend_by(secs).then_some(Message::Extend(secs)))
+                .width(Length::Fill)
+        };
+        widget::row()
+            .push(button(fl!("shorten-15m"), -15 * 60))
+            .push(button(fl!("extend-15m"), 15 * 60))
+            .push(button(fl!("extend-1h"), 60 * 60))
+            .spacing(spacing.space_xs)
+    });
 
     let action_button = if is_active {
         widget::button::destructive(fl!("stop-caffeine"))
@@ -480,17 +979,26 @@ fn build_popup_content(state: &AppModel) -> Element<'_, Message> {
             .width(Length::Fill)
     } else {
         widget::button::suggested(fl!("start-caffeine"))
-            .on_press(Message::ToggleCaffeine)
+            .on_press_maybe(can_start.then_some(Message::ToggleCaffeine))
             .width(Length::Fill)
     };
 
+    let settings_button = cosmic::applet::menu_button(widget::text::body(fl!("settings")))
+        .on_press(Message::OpenSettings);
+
     let content = widget::column()
         .push(header)
         .push(status_indicator)
         .push(widget::divider::horizontal::light())
         .push(options)
         .push(widget::divider::horizontal::light())
+        .push(mode_options)
+        .push(widget::divider::horizontal::light())
+        .push(scope_options)
+        .push(widget::divider::horizontal::light())
+        .push_maybe(extend_buttons)
         .push(action_button)
+        .push(settings_button)
         .spacing(spacing.space_s)
         .padding([spacing.space_s, spacing.space_m]);
 
@@ -1,84 +1,412 @@
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
+use zbus::Connection;
 
-use ashpd::desktop::inhibit::{InhibitFlags, InhibitProxy};
-use ashpd::desktop::Request;
-use ashpd::enumflags2::{make_bitflags, BitFlags};
+use crate::state::InhibitScope;
 
-const INHIBIT_FLAGS: BitFlags<InhibitFlags> = make_bitflags!(InhibitFlags::{Idle});
+mod gnome;
+mod logind;
+mod portal;
+mod screensaver;
+
+use gnome::GnomeSessionInhibitor;
+use logind::LogindInhibitor;
+use portal::PortalInhibitor;
+use screensaver::ScreenSaverInhibitor;
+
+pub use logind::{LogindManagerProxy, LogindMode, PrepareForSleep};
+
+/// Why the backend could not take or release an inhibit lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendError {
+    /// The inhibit service (portal, logind, ...) is not reachable.
+    Unavailable(String),
+    /// The service answered but refused or cancelled the request.
+    InhibitDenied(String),
+    /// An inhibit lock is already held by this backend.
+    AlreadyActive,
+    /// The held lock could not be closed.
+    ReleaseFailed(String),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(msg) => write!(f, "Inhibit service unavailable: {}", msg),
+            Self::InhibitDenied(msg) => write!(f, "Inhibit request denied: {}", msg),
+            Self::AlreadyActive => write!(f, "An inhibit lock is already held"),
+            Self::ReleaseFailed(msg) => write!(f, "Failed to close inhibit handle: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<zbus::Error> for BackendError {
+    fn from(e: zbus::Error) -> Self {
+        match &e {
+            zbus::Error::MethodError(name, _, _) => match name.as_str() {
+                "org.freedesktop.DBus.Error.ServiceUnknown"
+                | "org.freedesktop.DBus.Error.NameHasNoOwner"
+                | "org.freedesktop.DBus.Error.UnknownMethod"
+                | "org.freedesktop.DBus.Error.UnknownObject"
+                | "org.freedesktop.DBus.Error.UnknownInterface" => Self::Unavailable(e.to_string()),
+                _ => Self::InhibitDenied(e.to_string()),
+            },
+            _ => Self::Unavailable(e.to_string()),
+        }
+    }
+}
+
+/// Which inhibitor to use, set through the `backend` config key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackendKind {
+    /// Probe the buses and use the first service that answers.
+    #[default]
+    Auto,
+    /// `org.freedesktop.portal.Inhibit`
+    Portal,
+    /// `org.gnome.SessionManager`
+    GnomeSession,
+    /// `org.freedesktop.ScreenSaver`
+    ScreenSaver,
+    /// `org.freedesktop.login1.Manager` on the system bus
+    Logind,
+}
+
+impl BackendKind {
+    /// Order in which `Auto` tries the backends.
+    const PROBE_ORDER: [BackendKind; 4] = [
+        BackendKind::Portal,
+        BackendKind::GnomeSession,
+        BackendKind::ScreenSaver,
+        BackendKind::Logind,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BackendKind::Auto => "automatic",
+            BackendKind::Portal => "XDG portal",
+            BackendKind::GnomeSession => "GNOME session manager",
+            BackendKind::ScreenSaver => "org.freedesktop.ScreenSaver",
+            BackendKind::Logind => "systemd-logind",
+        }
+    }
+}
+
+/// Backend settings taken from the config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackendConfig {
+    pub kind: BackendKind,
+    pub logind_mode: LogindMode,
+    pub logind_lid_switch: bool,
+}
+
+impl BackendConfig {
+    fn inhibitor(
+        &self,
+        kind: BackendKind,
+        system: Option<&Connection>,
+    ) -> Option<Arc<dyn Inhibitor>> {
+        match kind {
+            BackendKind::Auto => None,
+            BackendKind::Portal => Some(Arc::new(PortalInhibitor)),
+            BackendKind::GnomeSession => Some(Arc::new(GnomeSessionInhibitor)),
+            BackendKind::ScreenSaver => Some(Arc::new(ScreenSaverInhibitor)),
+            BackendKind::Logind => Some(Arc::new(self.logind(system))),
+        }
+    }
+
+    fn logind(&self, system: Option<&Connection>) -> LogindInhibitor {
+        LogindInhibitor {
+            mode: self.logind_mode,
+            lid_switch: self.logind_lid_switch,
+            system: system.cloned(),
+        }
+    }
+}
+
+/// A service that can keep the session awake.
+pub trait Inhibitor: Send + Sync {
+    fn kind(&self) -> BackendKind;
+
+    /// Whether the service looks usable right now.
+    fn probe(&self) -> BoxFuture<'_, bool>;
+
+    /// Whether the lock goes away when `name` leaves the session bus.
+    fn holds_lock(&self, name: &str) -> bool;
+
+    /// Take a lock that prevents what `scope` covers until it is released.
+    fn inhibit<'a>(
+        &'a self,
+        reason: &'a str,
+        scope: InhibitScope,
+    ) -> BoxFuture<'a, Result<Box<dyn InhibitLock>, BackendError>>;
+}
+
+/// A lock taken by an `Inhibitor`.
+pub trait InhibitLock: Send {
+    fn release(self: Box<Self>) -> BoxFuture<'static, Result<(), BackendError>>;
+
+    /// Called instead of `release` once the service holding the lock has
+    /// gone. Most locks die with their owner, so by default it is dropped.
+    fn abandon(self: Box<Self>) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Whether `name` is owned, or can be activated, on `conn`.
+async fn name_available(conn: &Connection, name: &str) -> bool {
+    let Ok(dbus) = zbus::fdo::DBusProxy::new(conn).await else {
+        return false;
+    };
+    let Ok(bus_name) = zbus::names::BusName::try_from(name) else {
+        return false;
+    };
+    if dbus.name_has_owner(bus_name).await.unwrap_or(false) {
+        return true;
+    }
+    dbus.list_activatable_names()
+        .await
+        .map(|names| names.iter().any(|n| n.as_str() == name))
+        .unwrap_or(false)
+}
 
 #[derive(Clone)]
 pub struct CaffeineBackend {
+    config: BackendConfig,
+    /// The system bus for logind, or `None` to connect when needed.
+    system: Option<Connection>,
     state: Arc<Mutex<BackendState>>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Default)]
 struct BackendState {
-    inhibit_handle: Option<Request<()>>,
+    inhibitor: Option<Arc<dyn Inhibitor>>,
+    lock: Option<Box<dyn InhibitLock>>,
+    /// logind's sleep and lid switch locks, held next to `lock` when the
+    /// inhibitor is not logind itself.
+    sleep_lock: Option<Box<dyn InhibitLock>>,
+    scope: InhibitScope,
+    /// Reason the held lock was taken with, reused when it is re-taken.
+    reason: String,
+    /// The lock went away with its service and has not been re-taken yet.
+    lost: bool,
 }
 
 impl CaffeineBackend {
-    pub fn new() -> Self {
-        info!("CaffeineBackend initialized");
+    pub fn new(config: BackendConfig) -> Self {
+        info!(
+            "CaffeineBackend initialized ({} backend)",
+            config.kind.label()
+        );
         Self {
+            config,
+            system: None,
             state: Arc::new(Mutex::new(BackendState::default())),
         }
     }
 
-    pub async fn inhibit(&self, reason: &str) -> Result<(), String> {
-        info!(
-            "Attempting to inhibit idle via XDG portal, reason: {}",
-            reason
-        );
+    /// Talk to logind over `system` rather than a connection of its own.
+    pub fn with_system_bus(mut self, system: Connection) -> Self {
+        self.system = Some(system);
+        self
+    }
 
+    /// Pick the configured inhibitor, or probe for one in `Auto` mode.
+    async fn select(&self) -> Result<Arc<dyn Inhibitor>, BackendError> {
+        if let Some(inhibitor) = self
+            .config
+            .inhibitor(self.config.kind, self.system.as_ref())
         {
-            let state = self.state.lock().await;
-            if state.inhibit_handle.is_some() {
-                warn!("Already inhibiting, skipping duplicate request");
-                return Ok(());
-            }
+            return Ok(inhibitor);
         }
 
-        let proxy = InhibitProxy::new().await.map_err(|e| {
-            let msg = format!("Failed to create InhibitProxy: {}", e);
-            error!("{}", msg);
-            msg
-        })?;
+        for kind in BackendKind::PROBE_ORDER {
+            let Some(inhibitor) = self.config.inhibitor(kind, self.system.as_ref()) else {
+                continue;
+            };
+            debug!("Probing {} backend", kind.label());
+            if inhibitor.probe().await {
+                info!("Using {} backend", kind.label());
+                return Ok(inhibitor);
+            }
+        }
 
-        debug!("InhibitProxy created successfully");
+        Err(BackendError::Unavailable(
+            "no inhibit service found on the session or system bus".to_string(),
+        ))
+    }
 
-        let request = proxy
-            .inhibit(None, INHIBIT_FLAGS, reason)
+    /// Take logind's sleep and lid switch locks for `scope` next to
+    /// `inhibitor`, whose own locks do not keep logind from suspending the
+    /// machine on a power policy or when the lid closes. Best effort: a
+    /// missing logind leaves the session inhibitor on its own.
+    async fn sleep_lock(
+        &self,
+        inhibitor: &dyn Inhibitor,
+        reason: &str,
+        scope: InhibitScope,
+    ) -> Option<Box<dyn InhibitLock>> {
+        if inhibitor.kind() == BackendKind::Logind {
+            return None;
+        }
+        self.config
+            .logind(self.system.as_ref())
+            .inhibit_sleep(reason, scope)
             .await
-            .map_err(|e| {
-                let msg = format!("Failed to call inhibit: {}", e);
-                error!("{}", msg);
-                debug!("D-Bus error details: {:?}", e);
-                msg
-            })?;
+            .inspect_err(|e| warn!("Could not take logind sleep lock: {}", e))
+            .ok()
+            .flatten()
+    }
 
-        debug!("Inhibit request successful, handle obtained");
-        info!("Screen idle inhibition activated successfully");
+    /// Take the idle inhibit lock. Returns `BackendError::AlreadyActive` if
+    /// a lock is already held; callers may treat that as success.
+    pub async fn inhibit(&self, reason: &str) -> Result<(), BackendError> {
+        let mut state = self.state.lock().await;
+        if state.lock.is_some() {
+            warn!("Already inhibiting, skipping duplicate request");
+            return Err(BackendError::AlreadyActive);
+        }
+
+        let inhibitor = match state.inhibitor.clone() {
+            Some(inhibitor) => inhibitor,
+            None => self.select().await?,
+        };
+
+        match inhibitor.inhibit(reason, state.scope).await {
+            Ok(lock) => {
+                state.sleep_lock = self
+                    .sleep_lock(inhibitor.as_ref(), reason, state.scope)
+                    .await;
+                state.inhibitor = Some(inhibitor);
+                state.lock = Some(lock);
+                state.reason = reason.to_string();
+                state.lost = false;
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    "{} backend failed to inhibit: {}",
+                    inhibitor.kind().label(),
+                    e
+                );
+                if matches!(e, BackendError::Unavailable(_)) {
+                    // Probe again next time, another service may be up by then.
+                    state.inhibitor = None;
+                }
+                Err(e)
+            }
+        }
+    }
 
+    /// Change what the lock covers. A held lock is re-taken with the new
+    /// scope before the old one is released, so there is no gap between.
+    pub async fn set_scope(&self, scope: InhibitScope) -> Result<(), BackendError> {
         let mut state = self.state.lock().await;
-        state.inhibit_handle = Some(request);
+        if state.scope == scope {
+            return Ok(());
+        }
 
+        if state.lock.is_some() {
+            let Some(inhibitor) = state.inhibitor.clone() else {
+                return Err(BackendError::Unavailable(
+                    "no inhibitor selected".to_string(),
+                ));
+            };
+            info!("Re-taking inhibit lock with scope {:?}", scope);
+            let lock = inhibitor.inhibit(&state.reason, scope).await?;
+            let sleep_lock = self
+                .sleep_lock(inhibitor.as_ref(), &state.reason, scope)
+                .await;
+            if let Some(old) = state.lock.replace(lock) {
+                if let Err(e) = old.release().await {
+                    warn!("Failed to release previous inhibit lock: {}", e);
+                }
+            }
+            if let Some(old) = std::mem::replace(&mut state.sleep_lock, sleep_lock) {
+                if let Err(e) = old.release().await {
+                    warn!("Failed to release previous logind sleep lock: {}", e);
+                }
+            }
+        }
+
+        state.scope = scope;
         Ok(())
     }
 
-    pub async fn uninhibit(&self) -> Result<(), String> {
+    /// Whether a change of owner of `name` affects the lock we hold, or
+    /// the one we lost.
+    pub async fn depends_on(&self, name: &str) -> bool {
+        let state = self.state.lock().await;
+        (state.lock.is_some() || state.lost)
+            && state
+                .inhibitor
+                .as_ref()
+                .is_some_and(|inhibitor| inhibitor.holds_lock(name))
+    }
+
+    pub async fn is_lost(&self) -> bool {
+        self.state.lock().await.lost
+    }
+
+    /// Take the lock again, with the same reason and scope, after the
+    /// service holding it restarted or left the bus. Returns `Ok(false)`
+    /// when there was nothing to re-take.
+    pub async fn reacquire(&self) -> Result<bool, BackendError> {
+        let mut state = self.state.lock().await;
+        if let Some(old) = state.lock.take() {
+            old.abandon().await;
+            state.lost = true;
+        }
+        if !state.lost {
+            return Ok(false);
+        }
+
+        // In `Auto` mode probe again, the old service may be gone for good.
+        let inhibitor = match (self.config.kind, state.inhibitor.clone()) {
+            (BackendKind::Auto, _) | (_, None) => self.select().await?,
+            (_, Some(inhibitor)) => inhibitor,
+        };
+        let lock = inhibitor.inhibit(&state.reason, state.scope).await?;
+        info!(
+            "Inhibit restored through {} backend",
+            inhibitor.kind().label()
+        );
+
+        // logind keeps its own locks when a session service goes, but the
+        // one it was taken for may have been logind itself.
+        if state.sleep_lock.is_none() {
+            state.sleep_lock = self
+                .sleep_lock(inhibitor.as_ref(), &state.reason, state.scope)
+                .await;
+        }
+        state.inhibitor = Some(inhibitor);
+        state.lock = Some(lock);
+        state.lost = false;
+        Ok(true)
+    }
+
+    pub async fn uninhibit(&self) -> Result<(), BackendError> {
         info!("Attempting to uninhibit (release idle lock)");
 
         let mut state = self.state.lock().await;
+        state.lost = false;
+
+        if let Some(sleep_lock) = state.sleep_lock.take() {
+            if let Err(e) = sleep_lock.release().await {
+                warn!("Failed to release logind sleep lock: {}", e);
+            }
+        }
 
-        if let Some(handle) = state.inhibit_handle.take() {
+        if let Some(lock) = state.lock.take() {
             debug!("Closing inhibit handle");
-            handle.close().await.map_err(|e| {
-                let msg = format!("Failed to close inhibit handle: {}", e);
-                error!("{}", msg);
-                debug!("D-Bus error details: {:?}", e);
-                msg
+            lock.release().await.inspect_err(|e| {
+                error!("{}", e);
             })?;
 
             info!("Screen idle inhibition released successfully");
@@ -0,0 +1,83 @@
+use futures_util::future::BoxFuture;
+use tracing::info;
+use zbus::{proxy, Connection};
+
+use super::{name_available, BackendError, BackendKind, InhibitLock, Inhibitor};
+use crate::config::APP_ID;
+use crate::state::InhibitScope;
+
+#[proxy(
+    interface = "org.gnome.SessionManager",
+    default_service = "org.gnome.SessionManager",
+    default_path = "/org/gnome/SessionManager"
+)]
+trait SessionManager {
+    fn inhibit(
+        &self,
+        app_id: &str,
+        toplevel_xid: u32,
+        reason: &str,
+        flags: u32,
+    ) -> zbus::Result<u32>;
+
+    fn uninhibit(&self, inhibit_cookie: u32) -> zbus::Result<()>;
+}
+
+/// Inhibits through `org.gnome.SessionManager`.
+pub struct GnomeSessionInhibitor;
+
+/// Like the ScreenSaver cookie, this one dies with its connection.
+struct GnomeSessionLock {
+    proxy: SessionManagerProxy<'static>,
+    cookie: u32,
+}
+
+impl Inhibitor for GnomeSessionInhibitor {
+    fn kind(&self) -> BackendKind {
+        BackendKind::GnomeSession
+    }
+
+    fn probe(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async {
+            match Connection::session().await {
+                Ok(conn) => name_available(&conn, "org.gnome.SessionManager").await,
+                Err(_) => false,
+            }
+        })
+    }
+
+    fn holds_lock(&self, name: &str) -> bool {
+        name == "org.gnome.SessionManager"
+    }
+
+    fn inhibit<'a>(
+        &'a self,
+        reason: &'a str,
+        scope: InhibitScope,
+    ) -> BoxFuture<'a, Result<Box<dyn InhibitLock>, BackendError>> {
+        Box::pin(async move {
+            info!(
+                "Attempting to inhibit {:?} via org.gnome.SessionManager, reason: {}",
+                scope, reason
+            );
+
+            let conn = Connection::session().await?;
+            let proxy = SessionManagerProxy::new(&conn).await?;
+            let cookie = proxy.inhibit(APP_ID, 0, reason, scope.bits()).await?;
+
+            info!("Session inhibition activated, cookie {}", cookie);
+            Ok(Box::new(GnomeSessionLock { proxy, cookie }) as Box<dyn InhibitLock>)
+        })
+    }
+}
+
+impl InhibitLock for GnomeSessionLock {
+    fn release(self: Box<Self>) -> BoxFuture<'static, Result<(), BackendError>> {
+        Box::pin(async move {
+            self.proxy
+                .uninhibit(self.cookie)
+                .await
+                .map_err(|e| BackendError::ReleaseFailed(e.to_string()))
+        })
+    }
+}
@@ -0,0 +1,226 @@
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use zbus::zvariant::OwnedFd;
+use zbus::{proxy, Connection};
+
+use super::{name_available, BackendError, BackendKind, InhibitLock, Inhibitor};
+use crate::state::InhibitScope;
+
+const WHO: &str = "cosmic-caffeine";
+
+#[proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+pub trait LogindManager {
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+
+    /// Sent with `true` just before the machine suspends and `false` once
+    /// it has resumed.
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+/// How the logind sleep lock behaves, set through the `logind_mode` key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogindMode {
+    /// Refuse to suspend while the lock is held.
+    #[default]
+    Block,
+    /// Only hold off suspend for logind's `InhibitDelayMaxSec`.
+    Delay,
+}
+
+impl LogindMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogindMode::Block => "block",
+            LogindMode::Delay => "delay",
+        }
+    }
+}
+
+/// The `(what, mode)` pairs to pass to `Inhibit` for `scope`. logind only
+/// accepts delay locks for sleep and shutdown, so the idle and lid switch
+/// locks always block. It has no locks for logout or user switching.
+fn lock_requests(
+    scope: InhibitScope,
+    mode: LogindMode,
+    lid_switch: bool,
+) -> Vec<(String, &'static str)> {
+    let mut blocking = Vec::new();
+    if scope.suspend && mode == LogindMode::Block {
+        blocking.push("sleep");
+    }
+    if scope.idle {
+        blocking.push("idle");
+    }
+    if lid_switch {
+        blocking.push("handle-lid-switch");
+    }
+
+    let mut requests = Vec::new();
+    if scope.suspend && mode == LogindMode::Delay {
+        requests.push(("sleep".to_string(), mode.as_str()));
+    }
+    if !blocking.is_empty() {
+        requests.push((blocking.join(":"), "block"));
+    }
+    requests
+}
+
+/// Inhibits through systemd-logind on the system bus. Each lock is a file
+/// descriptor that logind watches; closing it releases the lock.
+pub struct LogindInhibitor {
+    pub mode: LogindMode,
+    /// Also keep logind from acting on the lid switch.
+    pub lid_switch: bool,
+    /// The system bus, or `None` to connect to it when needed.
+    pub system: Option<Connection>,
+}
+
+impl LogindInhibitor {
+    async fn system_bus(&self) -> zbus::Result<Connection> {
+        match &self.system {
+            Some(system) => Ok(system.clone()),
+            None => Connection::system().await,
+        }
+    }
+
+    /// Take only the sleep and lid switch locks for `scope`, to hold next
+    /// to an inhibitor that cannot stop logind suspending the machine.
+    /// Returns `Ok(None)` when there is neither to take.
+    pub async fn inhibit_sleep(
+        &self,
+        reason: &str,
+        scope: InhibitScope,
+    ) -> Result<Option<Box<dyn InhibitLock>>, BackendError> {
+        if !scope.suspend && !self.lid_switch {
+            return Ok(None);
+        }
+        let sleep = InhibitScope {
+            idle: false,
+            suspend: scope.suspend,
+            logout: false,
+            user_switch: false,
+        };
+        self.inhibit(reason, sleep).await.map(Some)
+    }
+}
+
+struct LogindLock(Vec<OwnedFd>);
+
+impl Inhibitor for LogindInhibitor {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Logind
+    }
+
+    fn probe(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async {
+            match self.system_bus().await {
+                Ok(conn) => name_available(&conn, "org.freedesktop.login1").await,
+                Err(_) => false,
+            }
+        })
+    }
+
+    fn holds_lock(&self, _name: &str) -> bool {
+        // logind lives on the system bus and keeps inhibitor fds across
+        // its own restarts.
+        false
+    }
+
+    fn inhibit<'a>(
+        &'a self,
+        reason: &'a str,
+        scope: InhibitScope,
+    ) -> BoxFuture<'a, Result<Box<dyn InhibitLock>, BackendError>> {
+        Box::pin(async move {
+            info!(
+                "Attempting to inhibit {:?} via systemd-logind, reason: {}",
+                scope, reason
+            );
+
+            let requests = lock_requests(scope, self.mode, self.lid_switch);
+            if requests.is_empty() {
+                return Err(BackendError::InhibitDenied(
+                    "systemd-logind cannot inhibit logout or user switching".to_string(),
+                ));
+            }
+
+            let conn = self.system_bus().await?;
+            let proxy = LogindManagerProxy::new(&conn).await?;
+
+            // If a later request fails, the fds taken so far are dropped
+            // with `fds` and their locks go away with them.
+            let mut fds = Vec::new();
+            for (what, mode) in requests {
+                fds.push(proxy.inhibit(&what, WHO, reason, mode).await?);
+                info!("logind {} lock taken ({})", what, mode);
+            }
+
+            Ok(Box::new(LogindLock(fds)) as Box<dyn InhibitLock>)
+        })
+    }
+}
+
+impl InhibitLock for LogindLock {
+    fn release(self: Box<Self>) -> BoxFuture<'static, Result<(), BackendError>> {
+        // Closing the fds is all logind needs.
+        drop(self.0);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE_AND_SUSPEND: InhibitScope = InhibitScope {
+        idle: true,
+        suspend: true,
+        logout: false,
+        user_switch: false,
+    };
+
+    #[test]
+    fn block_mode_takes_a_single_lock() {
+        assert_eq!(
+            lock_requests(IDLE_AND_SUSPEND, LogindMode::Block, false),
+            vec![("sleep:idle".to_string(), "block")]
+        );
+        assert_eq!(
+            lock_requests(IDLE_AND_SUSPEND, LogindMode::Block, true),
+            vec![("sleep:idle:handle-lid-switch".to_string(), "block")]
+        );
+    }
+
+    #[test]
+    fn delay_mode_only_delays_sleep() {
+        assert_eq!(
+            lock_requests(IDLE_AND_SUSPEND, LogindMode::Delay, true),
+            vec![
+                ("sleep".to_string(), "delay"),
+                ("idle:handle-lid-switch".to_string(), "block"),
+            ]
+        );
+    }
+
+    #[test]
+    fn scope_picks_the_locks() {
+        assert_eq!(
+            lock_requests(InhibitScope::default(), LogindMode::Block, false),
+            vec![("idle".to_string(), "block")]
+        );
+
+        let logout_only = InhibitScope {
+            idle: false,
+            suspend: false,
+            logout: true,
+            user_switch: false,
+        };
+        assert!(lock_requests(logout_only, LogindMode::Block, false).is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use futures_util::future::BoxFuture;
+use tracing::{debug, error, info};
+use zbus::Connection;
+
+use ashpd::desktop::inhibit::{InhibitFlags, InhibitProxy};
+use ashpd::desktop::Request;
+use ashpd::enumflags2::BitFlags;
+
+use super::{name_available, BackendError, BackendKind, InhibitLock, Inhibitor};
+use crate::state::InhibitScope;
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+
+fn inhibit_flags(scope: InhibitScope) -> BitFlags<InhibitFlags> {
+    let mut flags = BitFlags::empty();
+    if scope.logout {
+        flags |= InhibitFlags::Logout;
+    }
+    if scope.user_switch {
+        flags |= InhibitFlags::UserSwitch;
+    }
+    if scope.suspend {
+        flags |= InhibitFlags::Suspend;
+    }
+    if scope.idle {
+        flags |= InhibitFlags::Idle;
+    }
+    flags
+}
+
+impl From<ashpd::Error> for BackendError {
+    fn from(e: ashpd::Error) -> Self {
+        match e {
+            // Transport failures, e.g. no portal implementation on the bus.
+            ashpd::Error::Portal(ashpd::PortalError::ZBus(_)) => Self::Unavailable(e.to_string()),
+            // The portal received the call and said no.
+            ashpd::Error::Response(_) | ashpd::Error::Portal(_) => {
+                Self::InhibitDenied(e.to_string())
+            }
+            _ => Self::Unavailable(e.to_string()),
+        }
+    }
+}
+
+/// Inhibits through `org.freedesktop.portal.Inhibit`.
+pub struct PortalInhibitor;
+
+struct PortalLock(Request<()>);
+
+impl Inhibitor for PortalInhibitor {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Portal
+    }
+
+    fn probe(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async {
+            let Ok(conn) = Connection::session().await else {
+                return false;
+            };
+            // Creating the proxy checks that the Inhibit interface is there,
+            // not just that some portal frontend owns the name.
+            name_available(&conn, PORTAL_NAME).await && InhibitProxy::new().await.is_ok()
+        })
+    }
+
+    fn holds_lock(&self, name: &str) -> bool {
+        // The frontend, or the backend actually doing the inhibiting, e.g.
+        // the compositor's `org.freedesktop.impl.portal.desktop.cosmic`.
+        name == PORTAL_NAME || name.starts_with("org.freedesktop.impl.portal.desktop.")
+    }
+
+    fn inhibit<'a>(
+        &'a self,
+        reason: &'a str,
+        scope: InhibitScope,
+    ) -> BoxFuture<'a, Result<Box<dyn InhibitLock>, BackendError>> {
+        Box::pin(async move {
+            info!(
+                "Attempting to inhibit {:?} via XDG portal, reason: {}",
+                scope, reason
+            );
+
+            let proxy = InhibitProxy::new().await.map_err(|e| {
+                error!("Failed to create InhibitProxy: {}", e);
+                BackendError::Unavailable(e.to_string())
+            })?;
+
+            debug!("InhibitProxy created successfully");
+
+            let request = proxy
+                .inhibit(None, inhibit_flags(scope), reason)
+                .await
+                .map_err(|e| {
+                    error!("Failed to call inhibit: {}", e);
+                    debug!("D-Bus error details: {:?}", e);
+                    BackendError::from(e)
+                })?;
+
+            debug!("Inhibit request successful, handle obtained");
+            info!("Inhibition activated successfully");
+
+            Ok(Box::new(PortalLock(request)) as Box<dyn InhibitLock>)
+        })
+    }
+}
+
+impl InhibitLock for PortalLock {
+    fn release(self: Box<Self>) -> BoxFuture<'static, Result<(), BackendError>> {
+        Box::pin(async move {
+            self.0.close().await.map_err(|e| {
+                debug!("D-Bus error details: {:?}", e);
+                BackendError::ReleaseFailed(e.to_string())
+            })
+        })
+    }
+
+    fn abandon(self: Box<Self>) -> BoxFuture<'static, ()> {
+        // If only the portal backend restarted, the frontend still has the
+        // request and would keep it around.
+        Box::pin(async move {
+            if let Err(e) = self.0.close().await {
+                debug!("Could not close abandoned inhibit request: {}", e);
+            }
+        })
+    }
+}
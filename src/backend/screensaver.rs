@@ -0,0 +1,92 @@
+use futures_util::future::BoxFuture;
+use tracing::{info, warn};
+use zbus::{proxy, Connection};
+
+use super::{name_available, BackendError, BackendKind, InhibitLock, Inhibitor};
+use crate::state::InhibitScope;
+
+const APP_NAME: &str = "cosmic-caffeine";
+
+#[proxy(
+    interface = "org.freedesktop.ScreenSaver",
+    default_service = "org.freedesktop.ScreenSaver",
+    default_path = "/org/freedesktop/ScreenSaver"
+)]
+trait ScreenSaver {
+    fn inhibit(&self, application_name: &str, reason_for_inhibit: &str) -> zbus::Result<u32>;
+
+    fn un_inhibit(&self, cookie: u32) -> zbus::Result<()>;
+}
+
+/// Inhibits through `org.freedesktop.ScreenSaver`, as offered by KDE and
+/// most screen lockers.
+pub struct ScreenSaverInhibitor;
+
+/// The cookie is only valid on the connection that took it, so the proxy
+/// (and with it the connection) lives as long as the lock.
+struct ScreenSaverLock {
+    proxy: ScreenSaverProxy<'static>,
+    cookie: u32,
+}
+
+impl Inhibitor for ScreenSaverInhibitor {
+    fn kind(&self) -> BackendKind {
+        BackendKind::ScreenSaver
+    }
+
+    fn probe(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async {
+            match Connection::session().await {
+                Ok(conn) => name_available(&conn, "org.freedesktop.ScreenSaver").await,
+                Err(_) => false,
+            }
+        })
+    }
+
+    fn holds_lock(&self, name: &str) -> bool {
+        name == "org.freedesktop.ScreenSaver"
+    }
+
+    fn inhibit<'a>(
+        &'a self,
+        reason: &'a str,
+        scope: InhibitScope,
+    ) -> BoxFuture<'a, Result<Box<dyn InhibitLock>, BackendError>> {
+        Box::pin(async move {
+            if !scope.idle {
+                return Err(BackendError::InhibitDenied(
+                    "org.freedesktop.ScreenSaver can only inhibit idle".to_string(),
+                ));
+            }
+            if scope.suspend || scope.logout || scope.user_switch {
+                warn!(
+                    "org.freedesktop.ScreenSaver only inhibits idle, ignoring the rest of {:?}",
+                    scope
+                );
+            }
+
+            info!(
+                "Attempting to inhibit idle via org.freedesktop.ScreenSaver, reason: {}",
+                reason
+            );
+
+            let conn = Connection::session().await?;
+            let proxy = ScreenSaverProxy::new(&conn).await?;
+            let cookie = proxy.inhibit(APP_NAME, reason).await?;
+
+            info!("Screen idle inhibition activated, cookie {}", cookie);
+            Ok(Box::new(ScreenSaverLock { proxy, cookie }) as Box<dyn InhibitLock>)
+        })
+    }
+}
+
+impl InhibitLock for ScreenSaverLock {
+    fn release(self: Box<Self>) -> BoxFuture<'static, Result<(), BackendError>> {
+        Box::pin(async move {
+            self.proxy
+                .un_inhibit(self.cookie)
+                .await
+                .map_err(|e| BackendError::ReleaseFailed(e.to_string()))
+        })
+    }
+}
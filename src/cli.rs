@@ -0,0 +1,289 @@
+use clap::{Parser, Subcommand};
+use futures_util::StreamExt;
+use serde::Serialize;
+use tracing::{debug, error};
+use zbus::fdo::PropertiesProxy;
+use zbus::proxy::CacheProperties;
+
+use crate::duration;
+use crate::localtime::TimeOfDay;
+use crate::service::{CaffeineManagerProxy, DBUS_NAME, DBUS_PATH};
+use crate::state::{format_duration, InhibitScope, TimerSelection};
+
+/// Keep your COSMIC session awake.
+///
+/// Without arguments the panel applet is started.
+#[derive(Debug, Parser)]
+#[command(
+    name = "cosmic-caffeine",
+    version,
+    about,
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    /// Run the caffeine D-Bus service without any user interface
+    #[arg(long)]
+    pub daemon: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Everything that can be asked for on the command line.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Service(ServiceCommand),
+    /// Run a command and keep the session awake until it exits
+    Run {
+        /// The command and its arguments, e.g. `-- cargo build --release`
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
+
+/// Client commands, sent to the running applet or daemon over D-Bus.
+#[derive(Debug, Subcommand)]
+pub enum ServiceCommand {
+    /// Enable caffeine, indefinitely unless a duration or process is given
+    On {
+        /// How long to stay awake, e.g. `90` (minutes), `1h30m`, `2h`, `45s` or `1:30`
+        #[arg(
+            long = "for",
+            value_name = "DURATION",
+            value_parser = parse_duration,
+            group = "until"
+        )]
+        duration: Option<u64>,
+
+        /// Stay awake until the clock next reads this local time, e.g. `17:30`
+        #[arg(
+            long = "until",
+            value_name = "HH:MM",
+            value_parser = parse_time_of_day,
+            group = "until"
+        )]
+        until_time: Option<TimeOfDay>,
+
+        /// Stay awake for as long as the timer preset with this name
+        #[arg(long, value_name = "NAME", group = "until")]
+        preset: Option<String>,
+
+        /// Stay awake until the process with this pid exits
+        #[arg(long, value_name = "PID", group = "until")]
+        while_pid: Option<u32>,
+
+        /// Stay awake while a process with this name is running
+        #[arg(long, value_name = "NAME", group = "until")]
+        while_process: Option<String>,
+    },
+    /// Disable caffeine
+    Off,
+    /// Enable caffeine indefinitely if it is off, disable it otherwise
+    Toggle,
+    /// Print whether caffeine is active and how long is left
+    Status {
+        /// Print the state as a JSON object
+        #[arg(long)]
+        json: bool,
+
+        /// Keep running and print the state again every time it changes
+        #[arg(long)]
+        watch: bool,
+    },
+    /// List the timer presets that `on --preset` accepts
+    Presets,
+}
+
+/// What `status` prints, and the JSON shape of `status --json`.
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    active: bool,
+    selection: TimerSelection,
+    expiry_ts: i64,
+    remaining_secs: Option<u64>,
+    clients: u32,
+    scope: InhibitScope,
+    /// The selection as the popup names it, e.g. after the running preset.
+    #[serde(skip)]
+    label: String,
+}
+
+/// Parse a `--for` value into seconds, the same way as the popup's
+/// manual field.
+fn parse_duration(value: &str) -> Result<u64, String> {
+    duration::parse(value).map_err(|e| e.to_string())
+}
+
+/// Parse an `--until` value, written `HH:MM` on the 24 hour clock.
+fn parse_time_of_day(value: &str) -> Result<TimeOfDay, String> {
+    TimeOfDay::parse(value).ok_or_else(|| format!("invalid time '{}', expected e.g. 17:30", value))
+}
+
+/// Run a client command against the instance owning `DBUS_NAME`.
+pub fn run(command: ServiceCommand) -> zbus::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async {
+        let conn = zbus::Connection::session().await?;
+
+        let dbus = zbus::fdo::DBusProxy::new(&conn).await?;
+        if !dbus.name_has_owner(DBUS_NAME.try_into()?).await? {
+            debug!("No owner for {}", DBUS_NAME);
+            return Err(zbus::Error::Failure(
+                "caffeine is not running; add the applet to the panel or start `cosmic-caffeine --daemon`"
+                    .to_string(),
+            ));
+        }
+
+        // Read every property fresh, so `status` never races the cache.
+        let proxy = CaffeineManagerProxy::builder(&conn)
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        execute(&proxy, command).await
+    })
+}
+
+async fn execute(proxy: &CaffeineManagerProxy<'_>, command: ServiceCommand) -> zbus::Result<()> {
+    match command {
+        ServiceCommand::On {
+            duration,
+            until_time,
+            preset,
+            while_pid,
+            while_process,
+        } => {
+            let preset = preset.as_deref();
+            let while_process = while_process.as_deref();
+            let on = || {
+                enable(
+                    proxy,
+                    duration,
+                    until_time,
+                    preset,
+                    while_pid,
+                    while_process,
+                )
+            };
+            // The service refuses to replace a running session, but `on`
+            // restarts it so scripts can renew a timer.
+            match on().await {
+                Err(e) if is_already_active(&e) => {
+                    proxy.disable().await?;
+                    on().await
+                }
+                result => result,
+            }
+        }
+        ServiceCommand::Off => proxy.disable().await,
+        ServiceCommand::Toggle => {
+            if proxy.get_state().await?.is_active() {
+                proxy.disable().await
+            } else {
+                proxy.enable_indefinitely().await
+            }
+        }
+        ServiceCommand::Status { json, watch } => {
+            print_status(&fetch_status(proxy).await?, json);
+            if !watch {
+                return Ok(());
+            }
+
+            // Client inhibits and the scope change without a `StateChanged`.
+            let properties = PropertiesProxy::builder(proxy.inner().connection())
+                .destination(DBUS_NAME)?
+                .path(DBUS_PATH)?
+                .build()
+                .await?;
+            let properties = properties
+                .receive_properties_changed()
+                .await?
+                .filter(|signal| {
+                    futures_util::future::ready(signal.args().is_ok_and(|args| {
+                        let changed = args.changed_properties();
+                        changed.contains_key("Clients") || changed.contains_key("Scope")
+                    }))
+                })
+                .map(|_| ());
+            let states = proxy.receive_state_changed().await?.map(|_| ());
+            let mut changes = futures_util::stream::select(states, properties);
+            while changes.next().await.is_some() {
+                print_status(&fetch_status(proxy).await?, json);
+            }
+            Ok(())
+        }
+        ServiceCommand::Presets => {
+            for (name, secs) in proxy.presets().await? {
+                println!("{}\t{}", name, format_duration(secs));
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn enable(
+    proxy: &CaffeineManagerProxy<'_>,
+    duration: Option<u64>,
+    until_time: Option<TimeOfDay>,
+    preset: Option<&str>,
+    while_pid: Option<u32>,
+    while_process: Option<&str>,
+) -> zbus::Result<()> {
+    if let Some(pid) = while_pid {
+        proxy.enable_while_pid(pid).await
+    } else if let Some(name) = while_process {
+        proxy.enable_while_process(name).await
+    } else if let Some(time) = until_time {
+        proxy.enable_until_time(&time.to_string()).await
+    } else if let Some(name) = preset {
+        proxy.enable_preset(name).await
+    } else if let Some(secs) = duration {
+        proxy.enable_for(secs).await
+    } else {
+        proxy.enable_indefinitely().await
+    }
+}
+
+/// Whether the service refused to start a session because one is running.
+fn is_already_active(e: &zbus::Error) -> bool {
+    matches!(e, zbus::Error::MethodError(name, _, _) if name.ends_with(".Error.AlreadyActive"))
+}
+
+/// Read what `status` prints from the service.
+async fn fetch_status(proxy: &CaffeineManagerProxy<'_>) -> zbus::Result<StatusReport> {
+    let state = proxy.get_state().await?;
+    let name = proxy.selection_name().await?;
+    let selection = TimerSelection::from_name(&name)
+        .ok_or_else(|| zbus::Error::Failure(format!("unknown selection {}", name)))?;
+    let presets = proxy.presets().await?;
+    let label = selection.label_among(presets.iter().map(|(name, secs)| (name.as_str(), *secs)));
+    Ok(StatusReport {
+        active: state.is_active(),
+        selection,
+        expiry_ts: state.expiry_ts,
+        remaining_secs: u64::try_from(proxy.remaining_seconds().await?).ok(),
+        clients: proxy.clients().await?,
+        scope: proxy.scope().await?,
+        label,
+    })
+}
+
+fn print_status(report: &StatusReport, json: bool) {
+    if json {
+        match serde_json::to_string(report) {
+            Ok(line) => println!("{}", line),
+            Err(e) => error!("Failed to serialize state: {}", e),
+        }
+    } else if !report.active && report.clients > 0 {
+        println!("Kept awake by {} application(s)", report.clients);
+    } else if !report.active {
+        println!("Caffeine is off");
+    } else if let Some(secs) = report.remaining_secs {
+        println!("{} - {} remaining", report.label, format_duration(secs));
+    } else {
+        println!("{} mode active", report.label);
+    }
+}
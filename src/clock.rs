@@ -0,0 +1,192 @@
+use futures_util::future::BoxFuture;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Where timers get the time from, so tests can control it.
+pub trait Clock: Send + Sync {
+    /// Seconds since the Unix epoch by the wall clock. It jumps when the
+    /// system time is changed, e.g. by NTP, so only use it to show or take
+    /// times from people, never to measure how long something took.
+    fn unix_now(&self) -> u64;
+
+    /// Time since a fixed point, unaffected by changes to the system time.
+    /// Does not advance while the machine is suspended.
+    fn elapsed(&self) -> Duration;
+
+    /// Time spent suspended since a fixed point.
+    fn suspended(&self) -> Duration;
+
+    /// Resolve once `elapsed` reaches `deadline`.
+    fn sleep_until(&self, deadline: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The real clock, measuring time with the monotonic clock.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn unix_now(&self) -> u64 {
+        unix_now()
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn suspended(&self) -> Duration {
+        // CLOCK_BOOTTIME is CLOCK_MONOTONIC plus the time spent suspended.
+        clock_gettime(libc::CLOCK_BOOTTIME).saturating_sub(clock_gettime(libc::CLOCK_MONOTONIC))
+    }
+
+    fn sleep_until(&self, deadline: Duration) -> BoxFuture<'static, ()> {
+        let deadline = tokio::time::Instant::from_std(self.start + deadline);
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+/// Seconds since the Unix epoch by the system's wall clock.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs()
+}
+
+fn clock_gettime(clock: libc::clockid_t) -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid, writable timespec that outlives the call.
+    if unsafe { libc::clock_gettime(clock, &mut ts) } != 0 {
+        warn!(
+            "clock_gettime({}) failed: {}",
+            clock,
+            std::io::Error::last_os_error()
+        );
+        return Duration::ZERO;
+    }
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub struct FakeClock {
+    unix_now: std::sync::atomic::AtomicU64,
+    elapsed: tokio::sync::watch::Sender<Duration>,
+    suspended: std::sync::Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(unix_now: u64) -> Self {
+        Self {
+            unix_now: unix_now.into(),
+            elapsed: tokio::sync::watch::Sender::new(Duration::ZERO),
+            suspended: std::sync::Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Let `by` pass, waking any sleeper whose deadline it reaches.
+    pub fn advance(&self, by: Duration) {
+        use std::sync::atomic::Ordering;
+        self.unix_now.fetch_add(by.as_secs(), Ordering::SeqCst);
+        self.elapsed.send_modify(|elapsed| *elapsed += by);
+    }
+
+    /// Spend `by` suspended: the wall clock moves on, `elapsed` does not.
+    pub fn suspend(&self, by: Duration) {
+        use std::sync::atomic::Ordering;
+        self.unix_now.fetch_add(by.as_secs(), Ordering::SeqCst);
+        *self.suspended.lock().unwrap() += by;
+    }
+
+    /// Set the wall clock without any time passing, as NTP might.
+    pub fn set_unix_now(&self, unix_now: u64) {
+        use std::sync::atomic::Ordering;
+        self.unix_now.store(unix_now, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn unix_now(&self) -> u64 {
+        self.unix_now.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.borrow()
+    }
+
+    fn suspended(&self) -> Duration {
+        *self.suspended.lock().unwrap()
+    }
+
+    fn sleep_until(&self, deadline: Duration) -> BoxFuture<'static, ()> {
+        let mut elapsed = self.elapsed.subscribe();
+        Box::pin(async move {
+            let _ = elapsed.wait_for(|elapsed| *elapsed >= deadline).await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_clock_wall_time_jumps_without_time_passing() {
+        let clock = FakeClock::new(1_700_000_000);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.unix_now(), 1_700_000_090);
+        assert_eq!(clock.elapsed(), Duration::from_secs(90));
+
+        clock.set_unix_now(1_600_000_000);
+        assert_eq!(clock.unix_now(), 1_600_000_000);
+        assert_eq!(clock.elapsed(), Duration::from_secs(90));
+
+        clock.suspend(Duration::from_secs(10));
+        assert_eq!(clock.unix_now(), 1_600_000_010);
+        assert_eq!(clock.elapsed(), Duration::from_secs(90));
+        assert_eq!(clock.suspended(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn monotonic_clock_reads_suspended_time() {
+        let clock = MonotonicClock::new();
+        let before = clock.suspended();
+        assert!(clock.suspended() >= before);
+    }
+
+    #[test]
+    fn fake_clock_wakes_sleepers_at_their_deadline() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let clock = FakeClock::new(0);
+            let mut sleep = clock.sleep_until(Duration::from_secs(60));
+
+            clock.advance(Duration::from_secs(59));
+            assert!(futures_util::poll!(&mut sleep).is_pending());
+            clock.advance(Duration::from_secs(1));
+            assert!(futures_util::poll!(&mut sleep).is_ready());
+        });
+    }
+}
@@ -0,0 +1,196 @@
+use cosmic::cosmic_config::{self, cosmic_config_derive::CosmicConfigEntry, CosmicConfigEntry};
+use cosmic::iced::Color;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, warn};
+
+use crate::backend::{BackendConfig, BackendKind, LogindMode};
+use crate::localtime::TimeOfDay;
+use crate::notify::NotificationPolicy;
+use crate::state::{InhibitScope, SessionConfig, SuspendPolicy, TimerPreset};
+
+pub const APP_ID: &str = "com.github.cosmic-caffeine";
+
+/// The icon color while caffeine is on, unless `active_color` overrides it.
+const DEFAULT_ACTIVE_COLOR: &str = "#B22222";
+
+/// When "until tomorrow morning" ends, unless `morning_time` overrides it.
+const DEFAULT_MORNING_TIME: TimeOfDay = TimeOfDay { hour: 8, minute: 0 };
+
+/// What the popup's timer picker starts at, set through the `default_timer`
+/// key.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DefaultTimer {
+    #[default]
+    Infinity,
+    /// The preset with this name, or infinity once there is none.
+    Preset(String),
+    Manual,
+    Until,
+    Morning,
+}
+
+/// Settings stored through cosmic-config under `APP_ID`. The applet and the
+/// daemon follow changes as they are made; the backend, `suspend_policy`
+/// and `restore_infinity` keys are read at startup.
+#[derive(Debug, Clone, PartialEq, Eq, CosmicConfigEntry)]
+#[version = 1]
+pub struct Config {
+    /// Which inhibitor to use; `Auto` probes the session and system bus.
+    pub backend: BackendKind,
+    /// Whether the logind sleep lock blocks suspend or only delays it.
+    pub logind_mode: LogindMode,
+    /// Whether logind is also kept from suspending on the lid switch.
+    pub logind_lid_switch: bool,
+    /// Whether a timer keeps counting down while the machine is suspended.
+    pub suspend_policy: SuspendPolicy,
+    /// Whether infinity mode, left on when logging out, comes back on at
+    /// the next login.
+    pub restore_infinity: bool,
+    /// What the popup's manual field starts at, in minutes.
+    pub manual_minutes: u32,
+    /// Which timer the popup has picked when it opens.
+    pub default_timer: DefaultTimer,
+    /// The timers offered in the popup and by name over D-Bus.
+    pub presets: Vec<TimerPreset>,
+    /// Which notifications are shown.
+    pub notifications: NotificationPolicy,
+    /// The icon color while caffeine is on, as `#RRGGBB`.
+    pub active_color: String,
+    /// What the inhibit covers when caffeine starts.
+    pub default_scope: InhibitScope,
+    /// Processes that keep the session awake while any of them runs.
+    pub trigger_processes: Vec<String>,
+    /// When "until tomorrow morning" ends, as local `HH:MM`.
+    pub morning_time: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::default(),
+            logind_mode: LogindMode::default(),
+            logind_lid_switch: false,
+            suspend_policy: SuspendPolicy::default(),
+            restore_infinity: false,
+            manual_minutes: 30,
+            default_timer: DefaultTimer::default(),
+            presets: vec![
+                TimerPreset::new("1 Hour", 60),
+                TimerPreset::new("2 Hours", 120),
+            ],
+            notifications: NotificationPolicy::default(),
+            active_color: DEFAULT_ACTIVE_COLOR.to_string(),
+            default_scope: InhibitScope::default(),
+            trigger_processes: Vec::new(),
+            morning_time: DEFAULT_MORNING_TIME.to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the settings, using defaults for anything missing or unreadable.
+    pub fn load() -> Self {
+        match cosmic_config::Config::new(APP_ID, Self::VERSION) {
+            Ok(context) => match Self::get_entry(&context) {
+                Ok(config) => config,
+                Err((errors, config)) => {
+                    for e in errors {
+                        debug!("Using default for config key: {}", e);
+                    }
+                    config
+                }
+            },
+            Err(e) => {
+                error!("Failed to open config: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Call `changed` with the new settings each time they change on disk,
+    /// for as long as the returned watcher is kept.
+    pub fn watch(changed: impl Fn(Config) + Send + Sync + 'static) -> Option<impl Sized> {
+        Self::handler()?
+            .watch(move |context, _keys| match Self::get_entry(context) {
+                Ok(config) | Err((_, config)) => changed(config),
+            })
+            .map_err(|e| error!("Failed to watch config: {}", e))
+            .ok()
+    }
+
+    /// Open the settings for writing, as the settings window does.
+    pub fn handler() -> Option<cosmic_config::Config> {
+        cosmic_config::Config::new(APP_ID, Self::VERSION)
+            .map_err(|e| error!("Failed to open config: {}", e))
+            .ok()
+    }
+
+    pub fn backend_config(&self) -> BackendConfig {
+        BackendConfig {
+            kind: self.backend,
+            logind_mode: self.logind_mode,
+            logind_lid_switch: self.logind_lid_switch,
+        }
+    }
+
+    /// `active_color` as a color, or the default if it does not parse.
+    pub fn active_color(&self) -> Color {
+        let [r, g, b] = parse_hex_color(&self.active_color).unwrap_or_else(|| {
+            warn!(
+                "Ignoring active_color {:?}, expected #RRGGBB",
+                self.active_color
+            );
+            parse_hex_color(DEFAULT_ACTIVE_COLOR).unwrap_or_default()
+        });
+        Color::from_rgb8(r, g, b)
+    }
+
+    /// `morning_time` as a time of day, or the default if it does not parse.
+    pub fn morning_time(&self) -> TimeOfDay {
+        TimeOfDay::parse(&self.morning_time).unwrap_or_else(|| {
+            warn!(
+                "Ignoring morning_time {:?}, expected HH:MM",
+                self.morning_time
+            );
+            DEFAULT_MORNING_TIME
+        })
+    }
+
+    pub fn session_config(&self) -> SessionConfig {
+        SessionConfig {
+            suspend_policy: self.suspend_policy,
+            restore_infinity: self.restore_infinity,
+            scope: self.default_scope,
+            presets: self.presets.clone(),
+            trigger_processes: self.trigger_processes.clone(),
+        }
+    }
+}
+
+/// Parse a `#RRGGBB` color.
+fn parse_hex_color(value: &str) -> Option<[u8; 3]> {
+    let hex = value.trim().strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_color_reads_rrggbb() {
+        assert_eq!(parse_hex_color("#B22222"), Some([0xb2, 0x22, 0x22]));
+        assert_eq!(parse_hex_color(" #00ff7f "), Some([0x00, 0xff, 0x7f]));
+    }
+
+    #[test]
+    fn parse_hex_color_rejects_other_forms() {
+        for value in ["B22222", "#B222", "#B22222FF", "#GG0000", "#é2222", ""] {
+            assert_eq!(parse_hex_color(value), None, "{}", value);
+        }
+    }
+}
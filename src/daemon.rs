@@ -0,0 +1,74 @@
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+use zbus::Connection;
+
+use crate::config::Config;
+use crate::notify;
+use crate::service::{self, DBUS_NAME};
+
+/// Serve `CaffeineService` on the session bus until SIGINT or SIGTERM.
+///
+/// Used on sessions where the panel may be restarted or is not running at
+/// all, so the inhibit does not depend on the applet's lifetime.
+pub fn run(config: Config) -> zbus::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async {
+        let conn = zbus::Connection::session().await?;
+
+        notify::set_policy(config.notifications);
+        let (backend, session) = (config.backend_config(), config.session_config());
+        if !service::serve(&conn, backend, session).await? {
+            error!("{} is already owned by another instance", DBUS_NAME);
+            return Err(zbus::Error::NameTaken);
+        }
+
+        info!("Caffeine daemon running");
+
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sigint = signal(SignalKind::interrupt())?;
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+            _ = sigint.recv() => info!("Received SIGINT, shutting down"),
+            () = follow_config(&conn, config) => {}
+        }
+
+        Ok(())
+    })
+}
+
+/// Apply changes to the settings as they are made, the way the applet
+/// does. Never returns.
+async fn follow_config(conn: &Connection, mut config: Config) {
+    let (sender, mut changes) = mpsc::unbounded_channel();
+    let _watcher = Config::watch(move |config| {
+        let _ = sender.send(config);
+    });
+
+    while let Some(changed) = changes.recv().await {
+        notify::set_policy(changed.notifications);
+        if changed.default_scope != config.default_scope {
+            if let Err(e) = service::set_scope(conn, changed.default_scope).await {
+                error!("Failed to update scope: {}", e);
+            }
+        }
+        if changed.presets != config.presets {
+            if let Err(e) = service::set_presets(conn, changed.presets.clone()).await {
+                error!("Failed to update presets: {}", e);
+            }
+        }
+        if changed.trigger_processes != config.trigger_processes {
+            let triggers = changed.trigger_processes.clone();
+            if let Err(e) = service::set_triggers(conn, triggers).await {
+                error!("Failed to update trigger processes: {}", e);
+            }
+        }
+        config = changed;
+    }
+
+    // The watcher could not be started; keep serving without it.
+    std::future::pending().await
+}
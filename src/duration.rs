@@ -0,0 +1,224 @@
+use std::fmt;
+
+use crate::fl;
+
+/// Why a duration was not understood.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DurationError {
+    Empty,
+    Invalid,
+    Zero,
+    TooLong,
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            DurationError::Empty => fl!("duration-error-empty"),
+            DurationError::Invalid => fl!("duration-error-invalid"),
+            DurationError::Zero => fl!("duration-error-zero"),
+            DurationError::TooLong => fl!("duration-error-too-long"),
+        };
+        f.write_str(&message)
+    }
+}
+
+impl std::error::Error for DurationError {}
+
+/// The words accepted after a number for each unit, in lowercase.
+struct Units {
+    hours: Vec<String>,
+    minutes: Vec<String>,
+    seconds: Vec<String>,
+}
+
+impl Units {
+    /// The English units, plus those of the user's language.
+    fn localized() -> Self {
+        let words = |english: &[&str], localized: String| {
+            let mut words: Vec<String> = english.iter().map(|word| word.to_string()).collect();
+            words.extend(
+                localized
+                    .split(',')
+                    .map(|word| word.trim().to_lowercase())
+                    .filter(|word| !word.is_empty()),
+            );
+            words
+        };
+        Self {
+            hours: words(
+                &["h", "hr", "hrs", "hour", "hours"],
+                fl!("duration-units-hours"),
+            ),
+            minutes: words(
+                &["m", "min", "mins", "minute", "minutes"],
+                fl!("duration-units-minutes"),
+            ),
+            seconds: words(
+                &["s", "sec", "secs", "second", "seconds"],
+                fl!("duration-units-seconds"),
+            ),
+        }
+    }
+
+    fn factor(&self, unit: &str) -> Option<u64> {
+        let unit = unit.to_lowercase();
+        if self.hours.contains(&unit) {
+            Some(3600)
+        } else if self.minutes.contains(&unit) {
+            Some(60)
+        } else if self.seconds.contains(&unit) {
+            Some(1)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parse a duration typed by the user into seconds: a bare number of
+/// minutes (`90`), numbers with units (`1h30m`, `2 hours`, `45s`) or
+/// hours and minutes (`1:30`).
+pub fn parse(value: &str) -> Result<u64, DurationError> {
+    parse_with(value, &Units::localized())
+}
+
+fn parse_with(value: &str, units: &Units) -> Result<u64, DurationError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(DurationError::Empty);
+    }
+
+    let secs = if value.contains(':') {
+        parse_clock(value)?
+    } else if value.chars().all(|c| c.is_ascii_digit()) {
+        number(value)?
+            .checked_mul(60)
+            .ok_or(DurationError::TooLong)?
+    } else {
+        parse_units(value, units)?
+    };
+    if secs == 0 {
+        return Err(DurationError::Zero);
+    }
+    Ok(secs)
+}
+
+/// `H:MM` or `H:MM:SS`.
+fn parse_clock(value: &str) -> Result<u64, DurationError> {
+    let parts: Vec<&str> = value.split(':').map(str::trim).collect();
+    let (hours, rest) = match parts.as_slice() {
+        [hours, rest @ ..] if (1..=2).contains(&rest.len()) => (number(hours)?, rest),
+        _ => return Err(DurationError::Invalid),
+    };
+    let mut secs = hours.checked_mul(3600).ok_or(DurationError::TooLong)?;
+    for (part, factor) in rest.iter().zip([60, 1]) {
+        let amount = number(part)?;
+        if part.len() != 2 || amount >= 60 {
+            return Err(DurationError::Invalid);
+        }
+        secs = secs
+            .checked_add(amount * factor)
+            .ok_or(DurationError::TooLong)?;
+    }
+    Ok(secs)
+}
+
+/// Numbers each followed by a unit, e.g. `1h30m` or `1 hour 30 min`.
+fn parse_units(value: &str, units: &Units) -> Result<u64, DurationError> {
+    let mut secs: u64 = 0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(DurationError::Invalid);
+        }
+        let amount = number(&rest[..digits])?;
+        rest = rest[digits..].trim_start();
+
+        let word = rest
+            .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let factor = units.factor(&rest[..word]).ok_or(DurationError::Invalid)?;
+        rest = rest[word..].trim_start();
+
+        secs = amount
+            .checked_mul(factor)
+            .and_then(|amount| secs.checked_add(amount))
+            .ok_or(DurationError::TooLong)?;
+    }
+    Ok(secs)
+}
+
+fn number(digits: &str) -> Result<u64, DurationError> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(DurationError::Invalid);
+    }
+    digits.parse().map_err(|_| DurationError::TooLong)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english() -> Units {
+        let words = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+        Units {
+            hours: words(&["h", "hour", "hours"]),
+            minutes: words(&["m", "min", "minutes"]),
+            seconds: words(&["s"]),
+        }
+    }
+
+    #[test]
+    fn parse_reads_minutes_units_and_clock_times() {
+        let units = english();
+        for (value, secs) in [
+            ("90", 5400),
+            (" 90 ", 5400),
+            ("1h30m", 5400),
+            ("1h 30m", 5400),
+            ("1 hour 30 min", 5400),
+            ("2h", 7200),
+            ("2 Hours", 7200),
+            ("45s", 45),
+            ("1:30", 5400),
+            ("0:45", 2700),
+            ("1:00:30", 3630),
+        ] {
+            assert_eq!(parse_with(value, &units), Ok(secs), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parse_accepts_extra_unit_words() {
+        let mut units = english();
+        units.hours.push("std".to_string());
+        assert_eq!(parse_with("2std", &units), Ok(7200));
+        assert_eq!(parse_with("2std", &english()), Err(DurationError::Invalid));
+    }
+
+    #[test]
+    fn parse_rejects_bad_durations() {
+        let units = english();
+        for (value, error) in [
+            ("", DurationError::Empty),
+            ("  ", DurationError::Empty),
+            ("0", DurationError::Zero),
+            ("0h0m", DurationError::Zero),
+            ("0:00", DurationError::Zero),
+            ("h", DurationError::Invalid),
+            ("1x", DurationError::Invalid),
+            ("-5", DurationError::Invalid),
+            ("1.5h", DurationError::Invalid),
+            ("1:5", DurationError::Invalid),
+            ("1:75", DurationError::Invalid),
+            ("1:30:00:00", DurationError::Invalid),
+            ("99999999999999999999", DurationError::TooLong),
+            ("999999999999999999h", DurationError::TooLong),
+        ] {
+            assert_eq!(parse_with(value, &units), Err(error), "{:?}", value);
+        }
+    }
+}
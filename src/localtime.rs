@@ -0,0 +1,161 @@
+use std::fmt;
+
+/// A time of day on the local wall clock, e.g. `17:30`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u8, minute: u8) -> Option<Self> {
+        (hour < 24 && minute < 60).then_some(Self { hour, minute })
+    }
+
+    /// Parse `H:MM` or `HH:MM`, as written by `Display`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (hour, minute) = value.trim().split_once(':')?;
+        if !(1..=2).contains(&hour.len()) || minute.len() != 2 {
+            return None;
+        }
+        Self::new(hour.parse().ok()?, minute.parse().ok()?)
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+/// The local time of day at Unix time `ts`.
+pub fn time_of_day(ts: u64) -> Option<TimeOfDay> {
+    let tm = local(ts)?;
+    TimeOfDay::new(tm.tm_hour as u8, tm.tm_min as u8)
+}
+
+/// The first Unix time after `now` at which the local clock reads `time`,
+/// today or tomorrow. Follows the system time zone, so the answer is right
+/// across daylight saving changes; a time skipped when clocks go forward
+/// moves on by the size of the jump.
+pub fn next_occurrence(now: u64, time: TimeOfDay) -> Option<u64> {
+    let today = local(now)?;
+    for days in 0..2 {
+        let mut tm = today;
+        tm.tm_mday += days;
+        tm.tm_hour = time.hour.into();
+        tm.tm_min = time.minute.into();
+        tm.tm_sec = 0;
+        // Let mktime work out whether daylight saving applies then.
+        tm.tm_isdst = -1;
+        // SAFETY: `tm` is a valid, initialised struct tm.
+        let ts = unsafe { libc::mktime(&mut tm) };
+        match u64::try_from(ts) {
+            Ok(ts) if ts > now => return Some(ts),
+            Ok(_) => continue,
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+extern "C" {
+    /// Not bound by the libc crate.
+    fn tzset();
+}
+
+fn local(ts: u64) -> Option<libc::tm> {
+    let ts = libc::time_t::try_from(ts).ok()?;
+    // SAFETY: an all-zero struct tm is valid; localtime_r only writes to it.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: tzset has no preconditions. localtime_r gets valid pointers,
+    // and is not required to pick up changes to TZ by itself.
+    let converted = unsafe {
+        tzset();
+        libc::localtime_r(&ts, &mut tm)
+    };
+    (!converted.is_null()).then_some(tm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Set in the child processes started by `in_zone`.
+    const IN_ZONE: &str = "COSMIC_CAFFEINE_TEST_IN_ZONE";
+
+    /// Whether to run the checks of the test `name` here. Setting `TZ` in
+    /// this process would race the other tests' threads reading it, so the
+    /// first time round the test is run again in a child process started
+    /// with `TZ` set to `zone`, and only the child checks anything.
+    fn in_zone(zone: &str, name: &str) -> bool {
+        if std::env::var_os(IN_ZONE).is_some() {
+            return true;
+        }
+        let (_, module) = module_path!().split_once("::").unwrap();
+        let test = format!("{}::{}", module, name);
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([test.as_str(), "--exact", "--test-threads=1"])
+            .env("TZ", zone)
+            .env(IN_ZONE, "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success() && stdout.contains("1 passed"),
+            "{} failed in {}:\n{}",
+            test,
+            zone,
+            stdout
+        );
+        false
+    }
+
+    fn at(hour: u8, minute: u8) -> TimeOfDay {
+        TimeOfDay::new(hour, minute).unwrap()
+    }
+
+    #[test]
+    fn time_of_day_parses_what_it_prints() {
+        assert_eq!(TimeOfDay::parse("17:30"), Some(at(17, 30)));
+        assert_eq!(TimeOfDay::parse(" 9:05 "), Some(at(9, 5)));
+        assert_eq!(at(9, 5).to_string(), "09:05");
+        for value in ["24:00", "12:60", "12:5", "1230", "", "ab:cd", "123:00"] {
+            assert_eq!(TimeOfDay::parse(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn next_occurrence_is_today_or_tomorrow() {
+        // 2023-11-14 22:13:20 UTC
+        let now = 1_700_000_000;
+        if !in_zone("UTC0", "next_occurrence_is_today_or_tomorrow") {
+            return;
+        }
+        assert_eq!(time_of_day(now), Some(at(22, 13)));
+        assert_eq!(next_occurrence(now, at(23, 0)), Some(1_700_002_800));
+        assert_eq!(next_occurrence(now, at(8, 0)), Some(1_700_035_200));
+        assert_eq!(
+            next_occurrence(now, at(22, 13)),
+            Some(1_700_000_000 + 86_400 - 20)
+        );
+    }
+
+    #[test]
+    fn next_occurrence_follows_daylight_saving() {
+        let berlin = "CET-1CEST,M3.5.0,M10.5.0/3";
+        if !in_zone(berlin, "next_occurrence_follows_daylight_saving") {
+            return;
+        }
+        // 2024-03-30 22:00 CET; clocks go forward overnight.
+        let spring = 1_711_832_400;
+        assert_eq!(next_occurrence(spring, at(8, 0)), Some(spring + 9 * 3600));
+        // 2024-10-26 22:00 CEST; clocks go back overnight.
+        let autumn = 1_729_972_800;
+        assert_eq!(next_occurrence(autumn, at(8, 0)), Some(autumn + 11 * 3600));
+        // 02:30 does not exist on the night clocks go forward.
+        let skipped = next_occurrence(spring, at(2, 30)).unwrap();
+        assert!(skipped > spring && skipped <= spring + 6 * 3600);
+    }
+}
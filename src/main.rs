@@ -1,16 +1,33 @@
 mod app;
 mod backend;
+mod cli;
+mod clock;
+mod config;
+mod daemon;
+mod duration;
 mod i18n;
+mod localtime;
 mod notify;
+mod persist;
+mod process;
 mod service;
+mod settings;
 mod state;
+#[cfg(test)]
+mod testbus;
+mod wrap;
+
+use clap::Parser;
 
 fn main() -> cosmic::iced::Result {
+    let cli = cli::Cli::parse();
+
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
                 .add_directive(tracing::Level::INFO.into()),
         )
+        .with_writer(std::io::stderr)
         .init();
 
     if !is_cosmic_session() {
@@ -22,6 +39,28 @@ fn main() -> cosmic::iced::Result {
 
     i18n::init();
 
+    match cli.command {
+        Some(cli::Command::Run { command }) => {
+            std::process::exit(wrap::run(&command, config::Config::load().backend_config()))
+        }
+        Some(cli::Command::Service(command)) => {
+            if let Err(e) = cli::run(command) {
+                eprintln!("cosmic-caffeine: {}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if cli.daemon {
+        if let Err(e) = daemon::run(config::Config::load()) {
+            eprintln!("cosmic-caffeine: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     cosmic::applet::run::<app::AppModel>(())
 }
 
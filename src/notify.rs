@@ -1,12 +1,60 @@
 use notify_rust::{Notification, Urgency};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::thread;
 use tracing::{debug, warn};
 
+use crate::config::APP_ID;
 use crate::fl;
 
-const APP_ID: &str = "com.github.cosmic-caffeine";
+/// Which notifications are shown, set through the `notifications` key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum NotificationPolicy {
+    /// Every notification, including caffeine turning on and off.
+    #[default]
+    All = 0,
+    /// Only when a session ends by itself or something goes wrong.
+    Important = 1,
+    /// Only when something goes wrong.
+    ErrorsOnly = 2,
+}
+
+impl NotificationPolicy {
+    const ALL: [NotificationPolicy; 3] = [
+        NotificationPolicy::All,
+        NotificationPolicy::Important,
+        NotificationPolicy::ErrorsOnly,
+    ];
+}
+
+/// Read back a policy stored `as u8`.
+impl TryFrom<u8> for NotificationPolicy {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| *policy as u8 == value)
+            .ok_or(value)
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(NotificationPolicy::All as u8);
+
+/// Apply `policy` to every notification shown from now on.
+pub fn set_policy(policy: NotificationPolicy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+fn policy() -> NotificationPolicy {
+    NotificationPolicy::try_from(POLICY.load(Ordering::Relaxed)).unwrap_or_default()
+}
 
 pub fn notify_enabled() {
+    if policy() != NotificationPolicy::All {
+        return;
+    }
     let title = fl!("notification-enabled-title");
     let body = fl!("notification-enabled-body");
 
@@ -29,6 +77,9 @@ pub fn notify_enabled() {
 }
 
 pub fn notify_disabled() {
+    if policy() != NotificationPolicy::All {
+        return;
+    }
     let title = fl!("notification-disabled-title");
     let body = fl!("notification-disabled-body");
 
@@ -51,6 +102,9 @@ pub fn notify_disabled() {
 }
 
 pub fn notify_timer_expired() {
+    if policy() == NotificationPolicy::ErrorsOnly {
+        return;
+    }
     let title = fl!("notification-timer-expired-title");
     let body = fl!("notification-timer-expired-body");
 
@@ -72,6 +126,31 @@ pub fn notify_timer_expired() {
     });
 }
 
+pub fn notify_process_exited(process: &str) {
+    if policy() == NotificationPolicy::ErrorsOnly {
+        return;
+    }
+    let title = fl!("notification-process-exited-title");
+    let body = fl!("notification-process-exited-body", process = process);
+
+    thread::spawn(move || {
+        if let Err(e) = Notification::new()
+            .appname("Caffeine")
+            .summary(&title)
+            .body(&body)
+            .icon("caffeine")
+            .hint(notify_rust::Hint::DesktopEntry(APP_ID.to_string()))
+            .urgency(Urgency::Normal)
+            .timeout(5000)
+            .show()
+        {
+            warn!("Failed to show notification: {}", e);
+        } else {
+            debug!("Showed process exited notification");
+        }
+    });
+}
+
 pub fn notify_error(details: &str) {
     let title = fl!("notification-error-title");
     let body = format!("{} {}", fl!("notification-error-body"), details);
@@ -93,3 +172,38 @@ pub fn notify_error(details: &str) {
         }
     });
 }
+
+pub fn notify_inhibit_lost(details: &str) {
+    let title = fl!("notification-inhibit-lost-title");
+    let body = format!("{} {}", fl!("notification-inhibit-lost-body"), details);
+
+    thread::spawn(move || {
+        if let Err(e) = Notification::new()
+            .appname("Caffeine")
+            .summary(&title)
+            .body(&body)
+            .icon("dialog-warning")
+            .hint(notify_rust::Hint::DesktopEntry(APP_ID.to_string()))
+            .urgency(Urgency::Critical)
+            .timeout(10000)
+            .show()
+        {
+            warn!("Failed to show inhibit lost notification: {}", e);
+        } else {
+            debug!("Showed inhibit lost notification");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_survives_the_round_trip_through_u8() {
+        for policy in NotificationPolicy::ALL {
+            assert_eq!(NotificationPolicy::try_from(policy as u8), Ok(policy));
+        }
+        assert_eq!(NotificationPolicy::try_from(3), Err(3));
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+
+use crate::state::{InhibitScope, Session, TimerSelection};
+
+/// The user's session as written to disk, so a panel restart or a new login
+/// can take it up again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub active: bool,
+    pub selection: TimerSelection,
+    /// Unix time at which the session ends, or -1 when it has no timer.
+    pub expiry_ts: i64,
+    pub reason: String,
+    pub scope: InhibitScope,
+    /// `XDG_SESSION_ID` of the login that saved it, if known.
+    pub login: Option<String>,
+}
+
+impl SavedSession {
+    pub fn new(session: &Session, reason: String, scope: InhibitScope) -> Self {
+        Self {
+            active: session.active,
+            selection: session.selection,
+            expiry_ts: session.expiry_ts,
+            reason,
+            scope,
+            login: current_login(),
+        }
+    }
+
+    /// The selection to start again at Unix time `now`, with the seconds it
+    /// has left if it is timed. Timers come back while they have time left.
+    /// Infinity comes back within the same login, e.g. after the panel
+    /// restarted, and after a new login only with `restore_infinity`.
+    /// Process watches never come back, as the process may be long gone.
+    pub fn resumable(
+        &self,
+        now: u64,
+        login: Option<&str>,
+        restore_infinity: bool,
+    ) -> Option<(TimerSelection, Option<u64>)> {
+        if !self.active {
+            return None;
+        }
+        match self.selection {
+            TimerSelection::WhileProcess => None,
+            TimerSelection::Infinity => {
+                let same_login = login.is_some() && self.login.as_deref() == login;
+                (same_login || restore_infinity).then_some((TimerSelection::Infinity, None))
+            }
+            selection => {
+                let expiry_ts = u64::try_from(self.expiry_ts).ok()?;
+                let secs = expiry_ts.checked_sub(now).filter(|secs| *secs > 0)?;
+                Some((selection, Some(secs)))
+            }
+        }
+    }
+}
+
+/// The login session this process belongs to, as set by `pam_systemd`.
+pub fn current_login() -> Option<String> {
+    std::env::var("XDG_SESSION_ID")
+        .ok()
+        .filter(|id| !id.is_empty())
+}
+
+/// Where the session is saved: `$XDG_STATE_HOME/cosmic-caffeine/session.json`,
+/// falling back to `~/.local/state`.
+pub fn default_path() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+        })?;
+    Some(state_home.join("cosmic-caffeine").join("session.json"))
+}
+
+/// Read the saved session, if there is a readable one.
+pub fn load(path: &Path) -> Option<SavedSession> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to read saved session {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&data) {
+        Ok(saved) => Some(saved),
+        Err(e) => {
+            warn!("Ignoring malformed saved session {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Write `saved` to `path`, replacing the file in one step so a crash
+/// never leaves half of it behind.
+pub fn save(path: &Path, saved: &SavedSession) {
+    let write = || -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec_pretty(saved).map_err(std::io::Error::other)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, path)
+    };
+    match write() {
+        Ok(()) => debug!("Saved session to {}", path.display()),
+        Err(e) => warn!("Failed to save session to {}: {}", path.display(), e),
+    }
+}
+
+/// Saves the session to one path from a blocking thread, so the service
+/// never waits on the disk, and skips sessions that are already saved.
+#[derive(Clone)]
+pub struct Saver {
+    path: PathBuf,
+    /// The newest session handed to `save`.
+    latest: Arc<Mutex<Option<SavedSession>>>,
+    /// Held while writing, so writes never overlap.
+    writing: Arc<Mutex<()>>,
+}
+
+impl Saver {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            latest: Arc::new(Mutex::new(None)),
+            writing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Write `saved` in the background. Each write takes the newest
+    /// session when it starts, so an older one never ends up on disk last.
+    pub fn save(&self, saved: SavedSession) {
+        match self.latest.lock() {
+            Ok(mut latest) if latest.as_ref() != Some(&saved) => *latest = Some(saved),
+            _ => return,
+        }
+        let saver = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _writing = saver.writing.lock();
+            let latest = saver.latest.lock().ok().and_then(|latest| latest.clone());
+            if let Some(saved) = latest {
+                save(&saver.path, &saved);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn saved(selection: TimerSelection, expiry_ts: i64) -> SavedSession {
+        SavedSession {
+            active: true,
+            selection,
+            expiry_ts,
+            reason: "Testing".to_string(),
+            scope: InhibitScope::default(),
+            login: Some("3".to_string()),
+        }
+    }
+
+    #[test]
+    fn timer_resumes_with_the_time_it_has_left() {
+        let timer = saved(TimerSelection::Timer(7200), NOW as i64 + 600);
+        assert_eq!(
+            timer.resumable(NOW, Some("3"), false),
+            Some((TimerSelection::Timer(7200), Some(600)))
+        );
+        assert_eq!(
+            timer.resumable(NOW, Some("7"), false),
+            Some((TimerSelection::Timer(7200), Some(600)))
+        );
+        assert_eq!(timer.resumable(NOW + 600, Some("3"), false), None);
+    }
+
+    #[test]
+    fn infinity_resumes_after_a_new_login_only_when_asked() {
+        let infinity = saved(TimerSelection::Infinity, -1);
+        let resumed = Some((TimerSelection::Infinity, None));
+        assert_eq!(infinity.resumable(NOW, Some("3"), false), resumed);
+        assert_eq!(infinity.resumable(NOW, Some("7"), false), None);
+        assert_eq!(infinity.resumable(NOW, None, false), None);
+        assert_eq!(infinity.resumable(NOW, Some("7"), true), resumed);
+    }
+
+    #[test]
+    fn inactive_and_process_sessions_do_not_resume() {
+        let inactive = SavedSession {
+            active: false,
+            ..saved(TimerSelection::Timer(3600), NOW as i64 + 600)
+        };
+        assert_eq!(inactive.resumable(NOW, Some("3"), true), None);
+        let process = saved(TimerSelection::WhileProcess, -1);
+        assert_eq!(process.resumable(NOW, Some("3"), true), None);
+    }
+
+    #[test]
+    fn saved_session_survives_a_round_trip() {
+        let dir =
+            std::env::temp_dir().join(format!("cosmic-caffeine-persist-{}", std::process::id()));
+        let path = dir.join("session.json");
+        assert_eq!(load(&path), None);
+
+        let session = saved(TimerSelection::Timer(90), NOW as i64 + 90);
+        save(&path, &session);
+        assert_eq!(load(&path), Some(session));
+
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(load(&path), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use std::fs;
+use std::path::Path;
+
+/// A running process, told apart from any later one given the same pid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    /// When the process started, in clock ticks after boot.
+    start_time: u64,
+}
+
+impl Process {
+    /// The process running as `pid` right now, if any.
+    pub fn find(pid: u32) -> Option<Self> {
+        let stat = Stat::read(pid)?;
+        stat.is_alive().then_some(Self {
+            pid,
+            start_time: stat.start_time?,
+        })
+    }
+
+    /// Whether this process is still running, rather than gone with its
+    /// pid reused by another one.
+    pub fn is_alive(&self) -> bool {
+        Self::find(self.pid).as_ref() == Some(self)
+    }
+}
+
+/// The fields of `/proc/<pid>/stat` we use.
+struct Stat {
+    state: Option<String>,
+    start_time: Option<u64>,
+}
+
+impl Stat {
+    fn read(pid: u32) -> Option<Self> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The fields from the state on follow the parenthesised command
+        // name, which may itself contain spaces or parentheses.
+        let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+        Some(Self {
+            state: fields.first().map(|state| state.to_string()),
+            // Field 22 of the whole line, counting from 1.
+            start_time: fields.get(19).and_then(|ticks| ticks.parse().ok()),
+        })
+    }
+
+    fn is_alive(&self) -> bool {
+        !matches!(self.state.as_deref(), Some("Z") | Some("X") | None)
+    }
+}
+
+/// Whether `pid` refers to a running (non-zombie) process.
+pub fn pid_alive(pid: u32) -> bool {
+    Stat::read(pid).is_some_and(|stat| stat.is_alive())
+}
+
+/// Short name of `pid`, as shown by `ps`.
+pub fn process_name(pid: u32) -> Option<String> {
+    fs::read_to_string(format!("/proc/{}/comm", pid))
+        .ok()
+        .map(|comm| comm.trim_end().to_string())
+}
+
+/// Pids of all live processes whose name or executable is `name`.
+pub fn find_by_name(name: &str) -> Vec<u32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|&pid| matches_name(pid, name) && pid_alive(pid))
+        .collect()
+}
+
+fn matches_name(pid: u32, name: &str) -> bool {
+    if process_name(pid).as_deref() == Some(name) {
+        return true;
+    }
+    // `comm` is truncated to 15 bytes, so fall back to argv[0].
+    fs::read(format!("/proc/{}/cmdline", pid))
+        .ok()
+        .and_then(|cmdline| {
+            let argv0 = cmdline.split(|&b| b == 0).next()?.to_vec();
+            let argv0 = String::from_utf8(argv0).ok()?;
+            Path::new(&argv0)
+                .file_name()
+                .map(|file| file.to_string_lossy() == name)
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn own_process_is_alive_and_found_by_name() {
+        let pid = std::process::id();
+        assert!(pid_alive(pid));
+
+        let name = process_name(pid).unwrap();
+        assert!(find_by_name(&name).contains(&pid));
+    }
+
+    #[test]
+    fn missing_process_is_not_alive() {
+        assert!(!pid_alive(u32::MAX));
+        assert!(Process::find(u32::MAX).is_none());
+        assert!(find_by_name("no-such-caffeine-process").is_empty());
+    }
+
+    #[test]
+    fn process_with_a_reused_pid_is_not_the_same() {
+        let process = Process::find(std::process::id()).unwrap();
+        assert!(process.is_alive());
+
+        let before = Process {
+            start_time: process.start_time - 1,
+            ..process
+        };
+        assert!(!before.is_alive());
+    }
+}
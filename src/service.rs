@@ -1,126 +1,2276 @@
-use crate::backend::CaffeineBackend;
+use crate::backend::{
+    BackendConfig, BackendError, CaffeineBackend, LogindManagerProxy, PrepareForSleep,
+};
+use crate::clock::{Clock, MonotonicClock};
+use crate::duration;
+use crate::localtime::{self, TimeOfDay};
 use crate::notify;
-use crate::state::{CaffeineState, TimerSelection};
+use crate::persist::{self, SavedSession};
+use crate::process::{self, Process};
+use crate::state::{
+    format_duration, CaffeineState, InhibitMode, InhibitScope, SelectionKind, Session,
+    SessionConfig, SuspendPolicy, TimerPreset, TimerSelection,
+};
+use futures_util::StreamExt;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::{error, info};
-use zbus::{interface, object_server::SignalEmitter, proxy};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use zbus::message::Header;
+use zbus::names::OwnedUniqueName;
+use zbus::{interface, object_server::SignalEmitter, proxy, Connection, DBusError};
 
 pub const DBUS_NAME: &str = "com.github.oussama_berchi.cosmic_caffeine";
 pub const DBUS_PATH: &str = "/com/github/oussama_berchi/cosmic_caffeine";
-pub const DBUS_INTERFACE: &str = "com.github.oussama_berchi.cosmic_caffeine.Manager";
+
+/// How often a watched process is checked for having exited.
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Errors returned to D-Bus callers, named under
+/// `com.github.oussama_berchi.cosmic_caffeine.Error`.
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "com.github.oussama_berchi.cosmic_caffeine.Error")]
+pub enum CaffeineError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    /// No inhibit portal is reachable on the session bus.
+    PortalUnavailable(String),
+    /// The portal refused or cancelled the inhibit request.
+    InhibitDenied(String),
+    /// Caffeine is already on; `Disable` must be called first.
+    AlreadyActive(String),
+    /// No timer is running, so there is nothing to extend.
+    NoTimer(String),
+    /// The inhibit lock could not be released.
+    ReleaseFailed(String),
+    /// An argument was out of range or malformed.
+    InvalidArgs(String),
+}
+
+impl From<BackendError> for CaffeineError {
+    fn from(e: BackendError) -> Self {
+        match e {
+            BackendError::Unavailable(msg) => Self::PortalUnavailable(msg),
+            BackendError::InhibitDenied(msg) => Self::InhibitDenied(msg),
+            BackendError::AlreadyActive => Self::AlreadyActive(e.to_string()),
+            BackendError::ReleaseFailed(msg) => Self::ReleaseFailed(msg),
+        }
+    }
+}
+
+impl From<zbus::fdo::Error> for CaffeineError {
+    fn from(e: zbus::fdo::Error) -> Self {
+        Self::ZBus(e.into())
+    }
+}
 
 #[derive(Clone)]
 pub struct CaffeineService {
     backend: CaffeineBackend,
-    state: Arc<Mutex<CaffeineState>>,
+    clock: Arc<dyn Clock>,
+    session: Arc<Mutex<Session>>,
+    /// When the timed session ends, on `clock`'s monotonic time. The
+    /// published `expiry_ts` is only the wall clock's view of it.
+    deadline: Arc<Mutex<Option<Duration>>>,
+    suspend_policy: SuspendPolicy,
+    /// `clock.suspended()` when the timer last caught up with suspends.
+    suspended: Arc<Mutex<Duration>>,
+    session_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    clients: Arc<Mutex<ClientInhibits>>,
+    scope: Arc<Mutex<InhibitScope>>,
+    reason: Arc<Mutex<String>>,
+    presets: Arc<Mutex<Vec<TimerPreset>>>,
+    triggers: Arc<Mutex<Vec<String>>>,
+    /// Woken when `triggers` changes, to look for them straight away.
+    triggers_changed: Arc<Notify>,
+    /// Saves the user's session, if it is saved anywhere.
+    saver: Option<persist::Saver>,
+}
+
+/// Inhibits taken through `Inhibit`, modelled on org.freedesktop.ScreenSaver.
+#[derive(Debug, Default)]
+struct ClientInhibits {
+    next_cookie: u32,
+    entries: Vec<ClientInhibit>,
+    /// A trigger process is running, which counts as one more client.
+    trigger: bool,
+}
+
+#[derive(Debug)]
+struct ClientInhibit {
+    cookie: u32,
+    sender: OwnedUniqueName,
+    app_name: String,
 }
 
 impl CaffeineService {
-    pub fn new(backend: CaffeineBackend, state: Arc<Mutex<CaffeineState>>) -> Self {
-        Self { backend, state }
+    pub fn new(
+        backend: CaffeineBackend,
+        clock: Arc<dyn Clock>,
+        suspend_policy: SuspendPolicy,
+        session: Arc<Mutex<Session>>,
+        save_path: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            backend,
+            clock,
+            session,
+            deadline: Arc::new(Mutex::new(None)),
+            suspend_policy,
+            suspended: Arc::new(Mutex::new(Duration::ZERO)),
+            session_task: Arc::new(Mutex::new(None)),
+            clients: Arc::new(Mutex::new(ClientInhibits::default())),
+            scope: Arc::new(Mutex::new(InhibitScope::default())),
+            reason: Arc::new(Mutex::new(String::new())),
+            presets: Arc::new(Mutex::new(Vec::new())),
+            triggers: Arc::new(Mutex::new(Vec::new())),
+            triggers_changed: Arc::new(Notify::new()),
+            saver: save_path.map(persist::Saver::new),
+        }
     }
-}
 
-#[proxy(
-    interface = "com.github.oussama_berchi.cosmic_caffeine.Manager",
-    default_service = "com.github.oussama_berchi.cosmic_caffeine",
-    default_path = "/com/github/oussama_berchi/cosmic_caffeine"
-)]
-pub trait CaffeineManager {
-    async fn set_state(
+    fn current_session(&self) -> Session {
+        if let Ok(lock) = self.session.lock() {
+            *lock
+        } else {
+            error!("Failed to acquire lock on session");
+            Session::inactive()
+        }
+    }
+
+    /// Replace the user's session and return what was stored.
+    fn store_session(&self, new_session: Session) -> Session {
+        if let Ok(mut lock) = self.session.lock() {
+            *lock = new_session;
+        } else {
+            error!("Failed to acquire lock on session");
+        }
+        new_session
+    }
+
+    fn current_deadline(&self) -> Option<Duration> {
+        self.deadline
+            .lock()
+            .map(|deadline| *deadline)
+            .unwrap_or(None)
+    }
+
+    fn store_deadline(&self, deadline: Option<Duration>) {
+        if let Ok(mut lock) = self.deadline.lock() {
+            *lock = deadline;
+        }
+    }
+
+    /// Move the session's end to the Unix time `expiry_ts` and return the
+    /// new session.
+    fn store_expiry(&self, expiry_ts: i64) -> Session {
+        if let Ok(mut lock) = self.session.lock() {
+            lock.expiry_ts = expiry_ts;
+            *lock
+        } else {
+            error!("Failed to acquire lock on session");
+            self.current_session()
+        }
+    }
+
+    /// Return how long the machine was suspended since the last call, or
+    /// since the timer started.
+    fn take_suspended(&self) -> Duration {
+        let now = self.clock.suspended();
+        match self.suspended.lock() {
+            Ok(mut lock) => now.saturating_sub(std::mem::replace(&mut *lock, now)),
+            Err(_) => Duration::ZERO,
+        }
+    }
+
+    /// Seconds left in the timed session, by the monotonic clock.
+    fn remaining_secs(&self) -> Option<u64> {
+        if !self.current_session().is_active() {
+            return None;
+        }
+        let deadline = self.current_deadline()?;
+        Some(deadline.saturating_sub(self.clock.elapsed()).as_secs())
+    }
+
+    fn current_reason(&self) -> String {
+        self.reason
+            .lock()
+            .map(|reason| reason.clone())
+            .unwrap_or_default()
+    }
+
+    fn store_reason(&self, reason: String) {
+        if let Ok(mut lock) = self.reason.lock() {
+            *lock = reason;
+        }
+    }
+
+    fn current_scope(&self) -> InhibitScope {
+        self.scope.lock().map(|scope| *scope).unwrap_or_default()
+    }
+
+    fn store_scope(&self, scope: InhibitScope) {
+        if let Ok(mut lock) = self.scope.lock() {
+            *lock = scope;
+        }
+    }
+
+    fn current_presets(&self) -> Vec<TimerPreset> {
+        self.presets
+            .lock()
+            .map(|presets| presets.clone())
+            .unwrap_or_default()
+    }
+
+    fn store_presets(&self, presets: Vec<TimerPreset>) {
+        if let Ok(mut lock) = self.presets.lock() {
+            *lock = presets;
+        }
+    }
+
+    fn current_triggers(&self) -> Vec<String> {
+        self.triggers
+            .lock()
+            .map(|triggers| triggers.clone())
+            .unwrap_or_default()
+    }
+
+    fn store_triggers(&self, triggers: Vec<String>) {
+        if let Ok(mut lock) = self.triggers.lock() {
+            *lock = triggers;
+        }
+        self.triggers_changed.notify_one();
+    }
+
+    /// Write the user's session to disk so a later run can take it up.
+    fn save_session(&self, session: &Session) {
+        if let Some(saver) = &self.saver {
+            saver.save(SavedSession::new(
+                session,
+                self.current_reason(),
+                self.current_scope(),
+            ));
+        }
+    }
+
+    /// Take up the session saved by an earlier run, if it should resume.
+    async fn restore_session(
         &self,
-        active: bool,
-        selection_idx: u32,
-        manual_mins: u32,
-    ) -> zbus::Result<()>; // Client side uses standard Result
+        saved: SavedSession,
+        restore_infinity: bool,
+        emitter: &SignalEmitter<'_>,
+    ) {
+        if saved.scope != self.current_scope() {
+            if let Err(e) = self.apply_scope(saved.scope, emitter).await {
+                warn!("Failed to restore saved scope: {}", e);
+            }
+        }
 
-    async fn get_state(&self) -> zbus::Result<CaffeineState>;
-}
+        let login = persist::current_login();
+        let now = self.clock.unix_now();
+        let Some((selection, secs)) = saved.resumable(now, login.as_deref(), restore_infinity)
+        else {
+            debug!("Saved session has nothing to resume");
+            self.save_session(&self.current_session());
+            return;
+        };
 
-#[interface(name = "com.github.oussama_berchi.cosmic_caffeine.Manager")]
-impl CaffeineService {
-    async fn set_state(
-        &mut self,
-        active: bool,
-        selection_idx: u32,
-        manual_mins: u32,
-        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
-    ) -> zbus::fdo::Result<()> {
-        info!(
-            "D-Bus Request: SetState(active={}, idx={})",
-            active, selection_idx
+        info!("Resuming saved {} session", selection.name());
+        if let Err(e) = self
+            .start_session(selection, secs, saved.reason, emitter)
+            .await
+        {
+            warn!("Failed to resume saved session: {}", e);
+            self.save_session(&self.current_session());
+        }
+    }
+
+    /// Tell clients about `session`, both through `StateChanged` and through
+    /// `PropertiesChanged` for the properties that derive from it, and save
+    /// it.
+    async fn publish(&self, emitter: &SignalEmitter<'_>, session: &Session) {
+        self.save_session(session);
+
+        if let Err(e) = Self::state_changed(emitter, session.state()).await {
+            error!("Failed to emit signal: {}", e);
+        }
+
+        let changed = futures_util::try_join!(
+            self.active_changed(emitter),
+            self.selection_changed(emitter),
+            self.selection_name_changed(emitter),
+            self.expiry_timestamp_changed(emitter),
+            self.reason_changed(emitter),
         );
+        if let Err(e) = changed {
+            error!("Failed to emit property changes: {}", e);
+        }
+    }
 
-        let new_state = if active {
-            let selection = match selection_idx {
-                0 => TimerSelection::Infinity,
-                1 => TimerSelection::OneHour,
-                2 => TimerSelection::TwoHours,
-                _ => TimerSelection::Manual,
-            };
+    fn client_count(&self) -> u32 {
+        self.clients
+            .lock()
+            .map(|clients| clients.entries.len() as u32 + u32::from(clients.trigger))
+            .unwrap_or(0)
+    }
+
+    fn trigger_running(&self) -> bool {
+        self.clients
+            .lock()
+            .map(|clients| clients.trigger)
+            .unwrap_or(false)
+    }
+
+    fn store_trigger_running(&self, running: bool) {
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.trigger = running;
+        }
+    }
+
+    /// Tell clients the number of client inhibits changed.
+    async fn publish_clients(&self, emitter: &SignalEmitter<'_>) {
+        if let Err(e) = self.clients_changed(emitter).await {
+            error!("Failed to emit property changes: {}", e);
+        }
+    }
+
+    /// Release the backend inhibit once neither the user's session nor any
+    /// client still needs it.
+    async fn release_if_unused(&self) -> Result<(), BackendError> {
+        if self.current_session().is_active() || self.client_count() > 0 {
+            debug!("Inhibit still in use, keeping it");
+            return Ok(());
+        }
+        self.backend.uninhibit().await
+    }
+
+    /// Drop every inhibit held by `sender`, e.g. because it left the bus.
+    async fn drop_client(&self, sender: &str, emitter: &SignalEmitter<'_>) {
+        let dropped: Vec<ClientInhibit> = match self.clients.lock() {
+            Ok(mut clients) => {
+                let (dropped, kept) = std::mem::take(&mut clients.entries)
+                    .into_iter()
+                    .partition(|entry| entry.sender.as_str() == sender);
+                clients.entries = kept;
+                dropped
+            }
+            Err(_) => return,
+        };
+        if dropped.is_empty() {
+            return;
+        }
+
+        for entry in &dropped {
+            info!(
+                "Client {} ({}) vanished, dropping cookie {}",
+                entry.app_name, sender, entry.cookie
+            );
+        }
 
-            let manual_u64 = if manual_mins > 0 {
-                Some(manual_mins as u64)
-            } else {
-                None
+        if let Err(e) = self.release_if_unused().await {
+            error!("Failed to uninhibit after client vanished: {}", e);
+        }
+        self.publish_clients(emitter).await;
+    }
+
+    /// Follow `NameOwnerChanged`, restoring the inhibit when the service
+    /// holding it restarts and dropping the cookies of clients that
+    /// disconnect without calling `UnInhibit`.
+    async fn watch_clients(self, conn: Connection) -> zbus::Result<()> {
+        let dbus = zbus::fdo::DBusProxy::new(&conn).await?;
+        let mut changes = dbus.receive_name_owner_changed().await?;
+        let emitter = SignalEmitter::new(&conn, DBUS_PATH)?;
+
+        while let Some(change) = changes.next().await {
+            let Ok(args) = change.args() else {
+                continue;
             };
-            let duration = selection.duration_secs(manual_u64);
-
-            let expiry_ts = duration.map(|d| {
-                SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap_or(std::time::Duration::from_secs(0))
-                    .as_secs()
-                    + d
-            });
-
-            let reason = match selection {
-                TimerSelection::Infinity => "User enabled infinity caffeine mode".to_string(),
-                TimerSelection::OneHour => "User enabled 1-hour caffeine timer".to_string(),
-                TimerSelection::TwoHours => "User enabled 2-hour caffeine timer".to_string(),
-                TimerSelection::Manual => {
-                    format!("User enabled {}-minute caffeine timer", manual_mins)
+            match args.name() {
+                zbus::names::BusName::Unique(name) => {
+                    if args.new_owner().is_none() {
+                        self.drop_client(name.as_str(), &emitter).await;
+                    }
                 }
-            };
+                zbus::names::BusName::WellKnown(name) => {
+                    self.recover_inhibit(name.as_str(), args.old_owner().is_some(), &emitter)
+                        .await;
+                }
+            }
+        }
+        Ok(())
+    }
 
-            if let Err(e) = self.backend.inhibit(&reason).await {
-                error!("Failed to inhibit via D-Bus: {}", e);
-                notify::notify_error(&e);
-                return Ok(());
+    /// Keep the session awake while any trigger process runs, looking for
+    /// them every `PROCESS_POLL_INTERVAL` and whenever the list changes.
+    async fn watch_triggers(self, emitter: SignalEmitter<'static>) {
+        loop {
+            let names = self.current_triggers();
+            let running: Vec<String> = read_proc(move || {
+                names
+                    .into_iter()
+                    .filter(|name| !process::find_by_name(name).is_empty())
+                    .collect()
+            })
+            .await
+            .unwrap_or_default();
+            self.update_trigger(running, &emitter).await;
+
+            tokio::select! {
+                () = tokio::time::sleep(PROCESS_POLL_INTERVAL) => {}
+                () = self.triggers_changed.notified() => {}
             }
+        }
+    }
 
-            notify::notify_enabled();
-            CaffeineState::active(selection, expiry_ts)
-        } else {
-            if let Err(e) = self.backend.uninhibit().await {
-                error!("Failed to uninhibit via D-Bus: {}", e);
-                notify::notify_error(&e);
+    /// Take the inhibit for the trigger processes in `running`, or give it
+    /// up once none of them is left.
+    async fn update_trigger(&self, running: Vec<String>, emitter: &SignalEmitter<'_>) {
+        let held = self.trigger_running();
+        if !running.is_empty() && !held {
+            let reason = format!("{} running", running.join(", "));
+            info!("Trigger process running: {}", reason);
+            if let Err(e) = self.acquire(&reason).await {
+                error!("Failed to inhibit for trigger: {}", e);
+                return;
+            }
+            self.store_trigger_running(true);
+            self.publish_clients(emitter).await;
+        } else if running.is_empty() && held {
+            info!("No trigger process running, releasing");
+            self.store_trigger_running(false);
+            if let Err(e) = self.release_if_unused().await {
+                error!("Failed to release trigger inhibit: {}", e);
+            }
+            self.publish_clients(emitter).await;
+        }
+    }
+
+    /// Re-take the backend inhibit after the service holding it, e.g. the
+    /// portal or the compositor, restarted or left the bus.
+    async fn recover_inhibit(&self, name: &str, owner_gone: bool, emitter: &SignalEmitter<'_>) {
+        if !self.backend.depends_on(name).await {
+            return;
+        }
+        // A new owner only matters if an earlier attempt failed.
+        if !owner_gone && !self.backend.is_lost().await {
+            return;
+        }
+
+        info!("{} changed owner, restoring inhibit", name);
+        if let Err(e) = self.backend.reacquire().await {
+            warn!("Failed to restore inhibit after {} changed: {}", name, e);
+            notify::notify_inhibit_lost(&e.to_string());
+            // Nothing keeps the session awake any more, so stop claiming to.
+            // Client cookies are dropped, and triggers try again next poll.
+            if let Ok(mut clients) = self.clients.lock() {
+                clients.entries.clear();
+                clients.trigger = false;
+            }
+            if self.current_session().is_active() {
+                self.cancel_session_task();
+                self.end_session(emitter).await;
+            } else if let Err(e) = self.release_if_unused().await {
+                error!("Failed to uninhibit after losing the inhibit: {}", e);
             }
-            notify::notify_disabled();
-            CaffeineState::inactive()
+            self.publish_clients(emitter).await;
+        }
+    }
+
+    /// Catch the timer up after the machine resumed. The monotonic clock
+    /// stood still while suspended, so with `Count` the deadline is brought
+    /// forward by the time slept, and with `Pause` the published expiry is
+    /// pushed back by it instead.
+    async fn resumed(&self, emitter: &SignalEmitter<'_>) {
+        let slept = self.take_suspended();
+        let session = self.current_session();
+        let Some(deadline) = self.current_deadline() else {
+            return;
         };
+        if !session.is_active() || slept.is_zero() {
+            return;
+        }
 
-        {
-            if let Ok(mut lock) = self.state.lock() {
-                *lock = new_state;
-            } else {
-                error!("Failed to acquire lock on state");
+        // A session until a time of day ends then, however long it slept.
+        let policy = match session.selection {
+            TimerSelection::Until(_) => SuspendPolicy::Count,
+            _ => self.suspend_policy,
+        };
+        info!(
+            "Resumed after {}s suspended, applying {:?} policy",
+            slept.as_secs(),
+            policy
+        );
+        match policy {
+            SuspendPolicy::Count => {
+                let deadline = deadline.saturating_sub(slept);
+                self.store_deadline(Some(deadline));
+                self.schedule_expiry(deadline, emitter.to_owned());
+            }
+            SuspendPolicy::Pause => {
+                let slept = i64::try_from(slept.as_secs()).unwrap_or(i64::MAX);
+                let new_session = self.store_expiry(session.expiry_ts.saturating_add(slept));
+                self.publish(emitter, &new_session).await;
             }
         }
+    }
 
-        if let Err(e) = ctxt.emit(DBUS_INTERFACE, "StateChanged", &new_state).await {
-            error!("Failed to emit signal: {}", e);
+    /// Follow logind's `PrepareForSleep` until the stream ends.
+    async fn watch_sleep(
+        self,
+        mut sleeps: impl futures_util::Stream<Item = PrepareForSleep> + Unpin,
+        emitter: SignalEmitter<'static>,
+    ) {
+        while let Some(signal) = sleeps.next().await {
+            match signal.args() {
+                Ok(args) if args.start => debug!("System is suspending"),
+                Ok(_) => self.resumed(&emitter).await,
+                Err(e) => warn!("Malformed PrepareForSleep signal: {}", e),
+            }
+        }
+    }
+
+    fn cancel_session_task(&self) {
+        if let Ok(mut lock) = self.session_task.lock() {
+            if let Some(handle) = lock.take() {
+                debug!("Cancelling pending session task");
+                handle.abort();
+            }
+        }
+    }
+
+    /// Run `task` as the one that ends the current session, replacing any
+    /// timer or process watch that was running before.
+    fn spawn_session_task<F>(&self, task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.cancel_session_task();
+        let handle = tokio::spawn(task);
+        if let Ok(mut lock) = self.session_task.lock() {
+            *lock = Some(handle);
+        }
+    }
+
+    /// Spawn a task that releases the inhibit once the clock reaches
+    /// `deadline`, so timed sessions end even when no applet is around to
+    /// notice.
+    fn schedule_expiry(&self, deadline: Duration, emitter: SignalEmitter<'static>) {
+        let service = self.clone();
+        let expired = self.clock.sleep_until(deadline);
+        self.spawn_session_task(async move {
+            debug!(
+                "Expiry task sleeping for {}s",
+                deadline.saturating_sub(service.clock.elapsed()).as_secs()
+            );
+            expired.await;
+
+            // A newer request replaced the timer while we were waking up.
+            let current = service.current_session();
+            if !current.is_active() || service.current_deadline() != Some(deadline) {
+                debug!("Expiry task is stale, ignoring");
+                return;
+            }
+
+            info!("Timer expired, disabling caffeine");
+            service.end_session(&emitter).await;
+            notify::notify_timer_expired();
+        });
+    }
+
+    /// Spawn a task that releases the inhibit once `watched` reports that
+    /// the process it follows is gone.
+    fn watch_process<F>(&self, label: String, watched: F, emitter: SignalEmitter<'static>)
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        let service = self.clone();
+        let watched = Arc::new(watched);
+        self.spawn_session_task(async move {
+            loop {
+                let watched = watched.clone();
+                if !read_proc(move || watched()).await.unwrap_or(false) {
+                    break;
+                }
+                tokio::time::sleep(PROCESS_POLL_INTERVAL).await;
+            }
+
+            let current = service.current_session();
+            if !current.is_active() || current.selection != TimerSelection::WhileProcess {
+                debug!("Process watch is stale, ignoring");
+                return;
+            }
+
+            info!("Watched process {} exited, disabling caffeine", label);
+            service.end_session(&emitter).await;
+            notify::notify_process_exited(&label);
+        });
+    }
+
+    async fn end_session(&self, emitter: &SignalEmitter<'_>) {
+        // Called from the session task itself, so detach rather than abort.
+        if let Ok(mut lock) = self.session_task.lock() {
+            lock.take();
+        }
+
+        let new_session = self.store_session(Session::inactive());
+        self.store_reason(String::new());
+        self.store_deadline(None);
+        if let Err(e) = self.release_if_unused().await {
+            error!("Failed to uninhibit at end of session: {}", e);
+            notify::notify_error(&e.to_string());
+        }
+        self.publish(emitter, &new_session).await;
+    }
+
+    /// Fail with `AlreadyActive` if the user already has caffeine on.
+    fn ensure_inactive(&self) -> Result<(), CaffeineError> {
+        let session = self.current_session();
+        if session.is_active() {
+            return Err(CaffeineError::AlreadyActive(format!(
+                "Caffeine is already on ({}); disable it first",
+                session.selection.label()
+            )));
         }
         Ok(())
     }
 
-    async fn get_state(&self) -> CaffeineState {
-        if let Ok(lock) = self.state.lock() {
-            *lock
+    /// Take the backend inhibit, reusing the lock if clients already hold it.
+    async fn acquire(&self, reason: &str) -> Result<(), BackendError> {
+        match self.backend.inhibit(reason).await {
+            Ok(()) | Err(BackendError::AlreadyActive) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Start a user session with the given selection, lasting `secs` if
+    /// set, replacing whatever session was running before.
+    async fn start_session(
+        &self,
+        selection: TimerSelection,
+        secs: Option<u64>,
+        reason: String,
+        emitter: &SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        if let Err(e) = self.acquire(&reason).await {
+            error!("Failed to inhibit via D-Bus: {}", e);
+            notify::notify_error(&e.to_string());
+            return Err(e.into());
+        }
+
+        self.cancel_session_task();
+        notify::notify_enabled();
+        self.store_reason(reason);
+        let expiry_ts = secs.map(|secs| self.clock.unix_now().saturating_add(secs));
+        let new_session = self.store_session(Session::active(selection, expiry_ts));
+        let deadline = secs.map(|secs| self.clock.elapsed() + Duration::from_secs(secs));
+        self.store_deadline(deadline);
+        self.take_suspended();
+        if let Some(deadline) = deadline {
+            self.schedule_expiry(deadline, emitter.to_owned());
+        }
+        self.publish(emitter, &new_session).await;
+        Ok(())
+    }
+
+    /// Start a timed session ending `secs` from now.
+    async fn start_timer(
+        &self,
+        secs: u64,
+        emitter: &SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        let reason = format!("User enabled caffeine for {}", format_duration(secs));
+        self.start_timer_with_reason(secs, reason, emitter).await
+    }
+
+    async fn start_timer_with_reason(
+        &self,
+        secs: u64,
+        reason: String,
+        emitter: &SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        if secs == 0 {
+            return Err(CaffeineError::InvalidArgs(
+                "Duration must be greater than zero".to_string(),
+            ));
+        }
+        let fits = self
+            .clock
+            .unix_now()
+            .checked_add(secs)
+            .is_some_and(|ts| ts <= i64::MAX as u64);
+        if !fits {
+            return Err(CaffeineError::InvalidArgs(format!(
+                "Duration of {}s is too long",
+                secs
+            )));
+        }
+
+        self.start_session(TimerSelection::Timer(secs), Some(secs), reason, emitter)
+            .await
+    }
+
+    /// Move the end of the running timer by `secs`, later if positive and
+    /// earlier if negative. The inhibit is kept as it is.
+    async fn extend_timer(
+        &self,
+        secs: i64,
+        emitter: &SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        let no_timer =
+            || CaffeineError::NoTimer("No timer is running; start one first".to_string());
+        let session = self.current_session();
+        if !session.is_active() {
+            return Err(no_timer());
+        }
+        let Some(deadline) = self.current_deadline() else {
+            return Err(no_timer());
+        };
+        if secs == 0 {
+            return Err(CaffeineError::InvalidArgs(
+                "Extension must not be zero".to_string(),
+            ));
+        }
+
+        let change = Duration::from_secs(secs.unsigned_abs());
+        let (deadline, expiry_ts) = if secs > 0 {
+            match (
+                deadline.checked_add(change),
+                session.expiry_ts.checked_add(secs),
+            ) {
+                (Some(deadline), Some(expiry_ts)) => (deadline, expiry_ts),
+                _ => {
+                    return Err(CaffeineError::InvalidArgs(format!(
+                        "Extension of {}s is too long",
+                        secs
+                    )))
+                }
+            }
         } else {
-            error!("Failed to acquire lock on state");
-            CaffeineState::inactive()
+            let remaining = deadline.saturating_sub(self.clock.elapsed());
+            if remaining <= change {
+                return Err(CaffeineError::InvalidArgs(format!(
+                    "Cannot shorten the timer by {}, only {} is left",
+                    format_duration(change.as_secs()),
+                    format_duration(remaining.as_secs())
+                )));
+            }
+            (deadline - change, session.expiry_ts.saturating_add(secs))
+        };
+
+        // A 30m timer extended by 15m becomes a 45m one, and "Until 17:30"
+        // moved by a quarter of an hour becomes "Until 17:45".
+        let selection = match session.selection {
+            TimerSelection::Timer(length) if secs > 0 => {
+                TimerSelection::Timer(length.saturating_add(change.as_secs()))
+            }
+            TimerSelection::Timer(length) => {
+                TimerSelection::Timer(length.saturating_sub(change.as_secs()))
+            }
+            TimerSelection::Until(_) => u64::try_from(expiry_ts)
+                .ok()
+                .and_then(localtime::time_of_day)
+                .map_or(session.selection, TimerSelection::Until),
+            selection => selection,
+        };
+
+        info!("Moving the end of the timer by {}s", secs);
+        self.store_deadline(Some(deadline));
+        let new_session = self.store_session(Session {
+            selection,
+            expiry_ts,
+            ..session
+        });
+        self.schedule_expiry(deadline, emitter.to_owned());
+        self.publish(emitter, &new_session).await;
+        Ok(())
+    }
+
+    /// End the user's session on request, keeping any client inhibits.
+    async fn stop_session(&self, emitter: &SignalEmitter<'_>) -> Result<(), CaffeineError> {
+        self.cancel_session_task();
+
+        let new_session = self.store_session(Session::inactive());
+        self.store_reason(String::new());
+        self.store_deadline(None);
+        let released = self.release_if_unused().await;
+        match &released {
+            Ok(()) => notify::notify_disabled(),
+            Err(e) => {
+                error!("Failed to uninhibit via D-Bus: {}", e);
+                notify::notify_error(&e.to_string());
+            }
         }
+        self.publish(emitter, &new_session).await;
+        released.map_err(CaffeineError::from)
+    }
+
+    /// Switch the backend lock to `scope` and publish it.
+    async fn apply_scope(
+        &self,
+        scope: InhibitScope,
+        emitter: &SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        if scope.is_empty() {
+            return Err(CaffeineError::InvalidArgs(
+                "Scope must include at least one of idle, suspend, logout or user switch"
+                    .to_string(),
+            ));
+        }
+        if let Err(e) = self.backend.set_scope(scope).await {
+            error!("Failed to change inhibit scope: {}", e);
+            return Err(e.into());
+        }
+
+        self.store_scope(scope);
+        self.save_session(&self.current_session());
+        let changed =
+            futures_util::try_join!(self.scope_changed(emitter), self.mode_changed(emitter),);
+        if let Err(e) = changed {
+            error!("Failed to emit property changes: {}", e);
+        }
+        Ok(())
+    }
+
+    async fn enable_while(
+        &self,
+        label: String,
+        watched: impl Fn() -> bool + Send + Sync + 'static,
+        emitter: &SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        let reason = format!("Waiting for {} to exit", label);
+        self.start_session(TimerSelection::WhileProcess, None, reason, emitter)
+            .await?;
+        if self.current_session().selection == TimerSelection::WhileProcess {
+            self.watch_process(label, watched, emitter.to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// Claim `DBUS_NAME` on `conn` and export a fresh `CaffeineService` using
+/// the `backend` inhibitor, resuming the session saved by an earlier run.
+///
+/// Returns `Ok(false)` when another instance already owns the name, in which
+/// case the caller should act as a client of that instance.
+pub async fn serve(
+    conn: &Connection,
+    backend: BackendConfig,
+    session: SessionConfig,
+) -> zbus::Result<bool> {
+    let system = match Connection::system().await {
+        Ok(system) => Some(system),
+        Err(e) => {
+            warn!("Not following suspend and resume: {}", e);
+            None
+        }
+    };
+    let clock = Arc::new(MonotonicClock::new());
+    let save_path = persist::default_path();
+    serve_with_clock(conn, system, backend, session, clock, save_path).await
+}
+
+/// `serve`, listening for logind on `system`, timing sessions with `clock`
+/// and saving them to `save_path`.
+async fn serve_with_clock(
+    conn: &Connection,
+    system: Option<Connection>,
+    backend: BackendConfig,
+    session: SessionConfig,
+    clock: Arc<dyn Clock>,
+    save_path: Option<PathBuf>,
+) -> zbus::Result<bool> {
+    let saved = save_path.as_deref().and_then(persist::load);
+    let mut backend = CaffeineBackend::new(backend);
+    if let Some(system) = &system {
+        backend = backend.with_system_bus(system.clone());
+    }
+    let scope = if session.scope.is_empty() {
+        warn!("Ignoring empty default scope");
+        InhibitScope::default()
+    } else {
+        session.scope
+    };
+    if let Err(e) = backend.set_scope(scope).await {
+        warn!("Failed to apply default scope: {}", e);
+    }
+    let user_session = Arc::new(Mutex::new(Session::inactive()));
+    let service = CaffeineService::new(
+        backend,
+        clock,
+        session.suspend_policy,
+        user_session,
+        save_path,
+    );
+    service.store_scope(scope);
+    service.store_presets(session.presets);
+    service.store_triggers(session.trigger_processes);
+
+    // Export before claiming the name so no call can arrive too early.
+    conn.object_server().at(DBUS_PATH, service.clone()).await?;
+
+    match conn.request_name(DBUS_NAME).await {
+        Ok(_) => {}
+        Err(zbus::Error::NameTaken) => {
+            conn.object_server()
+                .remove::<CaffeineService, _>(DBUS_PATH)
+                .await?;
+            return Ok(false);
+        }
+        Err(e) => return Err(e),
+    }
+    info!("Acquired D-Bus name: {}", DBUS_NAME);
+
+    // Subscribe before returning so no resume is missed once sessions start.
+    if let Some(system) = system {
+        match receive_prepare_for_sleep(&system).await {
+            Ok(sleeps) => {
+                let emitter = SignalEmitter::new(conn, DBUS_PATH)?.into_owned();
+                tokio::spawn(service.clone().watch_sleep(sleeps, emitter));
+            }
+            Err(e) => warn!("Not following suspend and resume: {}", e),
+        }
+    }
+
+    if let Some(saved) = saved {
+        let emitter = SignalEmitter::new(conn, DBUS_PATH)?;
+        service
+            .restore_session(saved, session.restore_infinity, &emitter)
+            .await;
+    }
+
+    // Separate tasks, so failing to follow clients leaves triggers working.
+    let emitter = SignalEmitter::new(conn, DBUS_PATH)?.into_owned();
+    let triggers = tokio::spawn(service.clone().watch_triggers(emitter));
+    let conn = conn.clone();
+    tokio::spawn(async move {
+        match service.watch_clients(conn).await {
+            // The clients stream ends with the connection, and so do triggers.
+            Ok(()) => triggers.abort(),
+            Err(e) => error!("Failed to watch for vanished clients: {}", e),
+        }
+    });
+
+    Ok(true)
+}
+
+/// Hand `presets` to the service exported on `conn`, when this process is
+/// the one serving it.
+pub async fn set_presets(conn: &Connection, presets: Vec<TimerPreset>) -> zbus::Result<()> {
+    let Ok(iface) = conn
+        .object_server()
+        .interface::<_, CaffeineService>(DBUS_PATH)
+        .await
+    else {
+        debug!("Not serving {}, leaving presets to its owner", DBUS_NAME);
+        return Ok(());
+    };
+    let service = iface.get().await;
+    service.store_presets(presets);
+    service.presets_changed(iface.signal_emitter()).await
+}
+
+/// Switch the service exported on `conn` to `scope`, when this process is
+/// the one serving it.
+pub async fn set_scope(conn: &Connection, scope: InhibitScope) -> Result<(), CaffeineError> {
+    let Ok(iface) = conn
+        .object_server()
+        .interface::<_, CaffeineService>(DBUS_PATH)
+        .await
+    else {
+        debug!("Not serving {}, leaving the scope to its owner", DBUS_NAME);
+        return Ok(());
+    };
+    let service = iface.get().await;
+    service.apply_scope(scope, iface.signal_emitter()).await
+}
+
+/// Hand the trigger process names to the service exported on `conn`, when
+/// this process is the one serving it.
+pub async fn set_triggers(conn: &Connection, triggers: Vec<String>) -> zbus::Result<()> {
+    let Ok(iface) = conn
+        .object_server()
+        .interface::<_, CaffeineService>(DBUS_PATH)
+        .await
+    else {
+        debug!("Not serving {}, leaving triggers to its owner", DBUS_NAME);
+        return Ok(());
+    };
+    iface.get().await.store_triggers(triggers);
+    Ok(())
+}
+
+/// Run `read`, which reads from /proc, on a blocking thread rather than
+/// the runtime serving the bus. `None` if it panicked.
+async fn read_proc<T: Send + 'static>(read: impl FnOnce() -> T + Send + 'static) -> Option<T> {
+    tokio::task::spawn_blocking(read)
+        .await
+        .inspect_err(|e| error!("Failed to read /proc: {}", e))
+        .ok()
+}
+
+async fn receive_prepare_for_sleep(
+    system: &Connection,
+) -> zbus::Result<impl futures_util::Stream<Item = PrepareForSleep>> {
+    LogindManagerProxy::new(system)
+        .await?
+        .receive_prepare_for_sleep()
+        .await
+}
+
+#[proxy(
+    interface = "com.github.oussama_berchi.cosmic_caffeine.Manager",
+    default_service = "com.github.oussama_berchi.cosmic_caffeine",
+    default_path = "/com/github/oussama_berchi/cosmic_caffeine"
+)]
+pub trait CaffeineManager {
+    async fn set_state(
+        &self,
+        active: bool,
+        selection_idx: u32,
+        manual_mins: u32,
+    ) -> zbus::Result<()>; // Client side uses standard Result
+
+    async fn enable_indefinitely(&self) -> zbus::Result<()>;
+
+    async fn enable_for(&self, seconds: u64) -> zbus::Result<()>;
+
+    async fn enable_for_duration(&self, duration: &str) -> zbus::Result<()>;
+
+    async fn enable_preset(&self, name: &str) -> zbus::Result<()>;
+
+    async fn enable_until(&self, timestamp: u64) -> zbus::Result<()>;
+
+    async fn enable_until_time(&self, time: &str) -> zbus::Result<()>;
+
+    async fn extend(&self, seconds: i64) -> zbus::Result<()>;
+
+    async fn disable(&self) -> zbus::Result<()>;
+
+    async fn get_state(&self) -> zbus::Result<CaffeineState>;
+
+    async fn set_scope(&self, scope: InhibitScope) -> zbus::Result<()>;
+
+    async fn set_mode(&self, mode: &str) -> zbus::Result<()>;
+
+    async fn enable_while_pid(&self, pid: u32) -> zbus::Result<()>;
+
+    async fn enable_while_process(&self, name: &str) -> zbus::Result<()>;
+
+    async fn inhibit(&self, app_name: &str, reason: &str) -> zbus::Result<u32>;
+
+    async fn un_inhibit(&self, cookie: u32) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn state_changed(&self, state: CaffeineState) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn active(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn selection(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn selection_name(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn presets(&self) -> zbus::Result<Vec<(String, u64)>>;
+
+    #[zbus(property)]
+    fn expiry_timestamp(&self) -> zbus::Result<i64>;
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn remaining_seconds(&self) -> zbus::Result<i64>;
+
+    #[zbus(property)]
+    fn reason(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn scope(&self) -> zbus::Result<InhibitScope>;
+
+    #[zbus(property)]
+    fn mode(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn clients(&self) -> zbus::Result<u32>;
+}
+
+#[interface(name = "com.github.oussama_berchi.cosmic_caffeine.Manager")]
+impl CaffeineService {
+    /// Deprecated: use `EnableIndefinitely`, `EnableFor`, `EnableUntil` or
+    /// `Disable` instead. Selection indices are 0 = infinity, 1 = one hour,
+    /// 2 = two hours and 3 = `manual_mins` minutes.
+    async fn set_state(
+        &mut self,
+        active: bool,
+        selection_idx: u32,
+        manual_mins: u32,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!(
+            "D-Bus Request: SetState(active={}, idx={})",
+            active, selection_idx
+        );
+
+        if !active {
+            return self.stop_session(&ctxt).await;
+        }
+
+        match selection_idx {
+            0 => {
+                self.start_session(
+                    TimerSelection::Infinity,
+                    None,
+                    "User enabled infinity caffeine mode".to_string(),
+                    &ctxt,
+                )
+                .await
+            }
+            1 => self.start_timer(3600, &ctxt).await,
+            2 => self.start_timer(7200, &ctxt).await,
+            3 if manual_mins > 0 => self.start_timer(manual_mins as u64 * 60, &ctxt).await,
+            3 => Err(CaffeineError::InvalidArgs(
+                "Manual timer needs a duration in minutes".to_string(),
+            )),
+            idx => Err(CaffeineError::InvalidArgs(format!(
+                "Unknown selection index {}",
+                idx
+            ))),
+        }
+    }
+
+    /// Keep the session awake until `Disable` is called.
+    async fn enable_indefinitely(
+        &mut self,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: EnableIndefinitely()");
+        self.ensure_inactive()?;
+        self.start_session(
+            TimerSelection::Infinity,
+            None,
+            "User enabled infinity caffeine mode".to_string(),
+            &ctxt,
+        )
+        .await
+    }
+
+    /// Keep the session awake for `seconds` from now.
+    async fn enable_for(
+        &mut self,
+        seconds: u64,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: EnableFor(seconds={})", seconds);
+        self.ensure_inactive()?;
+        self.start_timer(seconds, &ctxt).await
+    }
+
+    /// Keep the session awake for `duration`, written as in the popup's
+    /// manual field, e.g. `90` (minutes), `1h30m` or `1:30`.
+    async fn enable_for_duration(
+        &mut self,
+        duration: String,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: EnableForDuration(duration={})", duration);
+        self.ensure_inactive()?;
+
+        let seconds = duration::parse(&duration).map_err(|e| {
+            CaffeineError::InvalidArgs(format!("Invalid duration {}: {}", duration, e))
+        })?;
+        self.start_timer(seconds, &ctxt).await
+    }
+
+    /// Keep the session awake for as long as the preset called `name`.
+    async fn enable_preset(
+        &mut self,
+        name: String,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: EnablePreset(name={})", name);
+        self.ensure_inactive()?;
+
+        let presets = self.current_presets();
+        let Some(preset) = TimerPreset::find(&presets, &name) else {
+            return Err(CaffeineError::InvalidArgs(format!(
+                "No preset named {}",
+                name
+            )));
+        };
+        let reason = format!("User enabled the {} preset", preset.name);
+        self.start_timer_with_reason(preset.secs(), reason, &ctxt)
+            .await
+    }
+
+    /// Keep the session awake until the Unix time `timestamp`.
+    async fn enable_until(
+        &mut self,
+        timestamp: u64,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: EnableUntil(timestamp={})", timestamp);
+        self.ensure_inactive()?;
+
+        let now = self.clock.unix_now();
+        if timestamp <= now {
+            return Err(CaffeineError::InvalidArgs(format!(
+                "Timestamp {} is not in the future",
+                timestamp
+            )));
+        }
+        self.start_timer(timestamp - now, &ctxt).await
+    }
+
+    /// Keep the session awake until the local clock next reads `time`,
+    /// given as `HH:MM`, today or tomorrow.
+    async fn enable_until_time(
+        &mut self,
+        time: String,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: EnableUntilTime(time={})", time);
+        self.ensure_inactive()?;
+
+        let Some(time) = TimeOfDay::parse(&time) else {
+            return Err(CaffeineError::InvalidArgs(format!(
+                "Invalid time {}, expected HH:MM",
+                time
+            )));
+        };
+        let now = self.clock.unix_now();
+        let Some(expiry_ts) = localtime::next_occurrence(now, time) else {
+            return Err(
+                zbus::fdo::Error::Failed(format!("Could not work out when {} is", time)).into(),
+            );
+        };
+        self.start_session(
+            TimerSelection::Until(time),
+            Some(expiry_ts - now),
+            format!("User enabled caffeine until {}", time),
+            &ctxt,
+        )
+        .await
+    }
+
+    /// Move the end of the running timer by `seconds`, which may be
+    /// negative to shorten it, without releasing the inhibit.
+    async fn extend(
+        &mut self,
+        seconds: i64,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: Extend(seconds={})", seconds);
+        self.extend_timer(seconds, &ctxt).await
+    }
+
+    /// End the user's session. Inhibits held through `Inhibit` are kept.
+    async fn disable(
+        &mut self,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: Disable()");
+        self.stop_session(&ctxt).await
+    }
+
+    async fn get_state(&self) -> CaffeineState {
+        self.current_session().state()
+    }
+
+    /// Choose what the inhibit covers. Applies to the running session and
+    /// client inhibits straight away, and to everything started later.
+    async fn set_scope(
+        &mut self,
+        scope: InhibitScope,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: SetScope({:?})", scope);
+        self.apply_scope(scope, &ctxt).await
+    }
+
+    /// Switch between `Full`, which keeps the screen on, and `AllowLock`,
+    /// which only blocks suspend and lets the screen lock as usual.
+    async fn set_mode(
+        &mut self,
+        mode: String,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: SetMode(mode={})", mode);
+
+        let Some(mode) = InhibitMode::from_name(&mode) else {
+            return Err(CaffeineError::InvalidArgs(format!(
+                "Unknown mode {}, expected Full or AllowLock",
+                mode
+            )));
+        };
+        let scope = self.current_scope().with_mode(mode);
+        self.apply_scope(scope, &ctxt).await
+    }
+
+    /// Take an inhibit on behalf of the caller, released by `UnInhibit` or
+    /// when the caller leaves the bus. Returns the cookie identifying it.
+    async fn inhibit(
+        &self,
+        app_name: String,
+        reason: String,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<u32, CaffeineError> {
+        let sender = header
+            .sender()
+            .ok_or_else(|| zbus::fdo::Error::Failed("Message has no sender".to_string()))?;
+        info!(
+            "D-Bus Request: Inhibit(app_name={}, reason={}) from {}",
+            app_name, reason, sender
+        );
+
+        if let Err(e) = self.acquire(&format!("{}: {}", app_name, reason)).await {
+            error!("Failed to inhibit for {}: {}", app_name, e);
+            return Err(e.into());
+        }
+
+        let cookie = match self.clients.lock() {
+            Ok(mut clients) => {
+                clients.next_cookie = clients.next_cookie.wrapping_add(1).max(1);
+                let cookie = clients.next_cookie;
+                clients.entries.push(ClientInhibit {
+                    cookie,
+                    sender: sender.to_owned().into(),
+                    app_name,
+                });
+                cookie
+            }
+            Err(_) => {
+                return Err(zbus::fdo::Error::Failed(
+                    "Failed to acquire lock on clients".to_string(),
+                )
+                .into())
+            }
+        };
+
+        self.publish_clients(&ctxt).await;
+        Ok(cookie)
+    }
+
+    /// Release an inhibit taken by the caller with `Inhibit`.
+    async fn un_inhibit(
+        &self,
+        cookie: u32,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: UnInhibit(cookie={})", cookie);
+
+        let sender = header.sender().map(|s| s.as_str());
+        let removed = match self.clients.lock() {
+            Ok(mut clients) => {
+                let before = clients.entries.len();
+                clients.entries.retain(|entry| {
+                    entry.cookie != cookie || Some(entry.sender.as_str()) != sender
+                });
+                clients.entries.len() != before
+            }
+            Err(_) => false,
+        };
+        if !removed {
+            return Err(CaffeineError::InvalidArgs(format!(
+                "Unknown cookie {}",
+                cookie
+            )));
+        }
+
+        if let Err(e) = self.release_if_unused().await {
+            error!("Failed to uninhibit via D-Bus: {}", e);
+            return Err(e.into());
+        }
+
+        self.publish_clients(&ctxt).await;
+        Ok(())
+    }
+
+    /// Keep caffeine on until the process `pid` exits.
+    async fn enable_while_pid(
+        &mut self,
+        pid: u32,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: EnableWhilePid(pid={})", pid);
+        self.ensure_inactive()?;
+
+        let found = read_proc(move || Some((Process::find(pid)?, process::process_name(pid))));
+        let Some((watched, name)) = found.await.flatten() else {
+            return Err(CaffeineError::InvalidArgs(format!(
+                "No process with pid {}",
+                pid
+            )));
+        };
+
+        let label = match name {
+            Some(name) => format!("{} ({})", name, pid),
+            None => format!("process {}", pid),
+        };
+        self.enable_while(label, move || watched.is_alive(), &ctxt)
+            .await
+    }
+
+    /// Keep caffeine on while any process called `name` is running.
+    async fn enable_while_process(
+        &mut self,
+        name: String,
+        #[zbus(signal_emitter)] ctxt: SignalEmitter<'_>,
+    ) -> Result<(), CaffeineError> {
+        info!("D-Bus Request: EnableWhileProcess(name={})", name);
+        self.ensure_inactive()?;
+
+        let scanned = name.clone();
+        let running = read_proc(move || !process::find_by_name(&scanned).is_empty()).await;
+        if running != Some(true) {
+            return Err(CaffeineError::InvalidArgs(format!(
+                "No running process named {}",
+                name
+            )));
+        }
+
+        let label = name.clone();
+        self.enable_while(
+            label,
+            move || !process::find_by_name(&name).is_empty(),
+            &ctxt,
+        )
+        .await
+    }
+
+    /// Emitted with the full state whenever the user's session changes.
+    #[zbus(signal)]
+    async fn state_changed(emitter: &SignalEmitter<'_>, state: CaffeineState) -> zbus::Result<()>;
+
+    /// Whether the user has caffeine turned on.
+    #[zbus(property)]
+    async fn active(&self) -> bool {
+        self.current_session().is_active()
+    }
+
+    /// The kind of session that is running, e.g. `OneHour` or `Infinity`.
+    /// Timers of other lengths read `Manual`.
+    #[zbus(property)]
+    async fn selection(&self) -> String {
+        let kind = SelectionKind::from(self.current_session().selection);
+        kind.name().to_string()
+    }
+
+    /// The session that is running, e.g. `Timer:5400`, `Until:17:30` or
+    /// `Infinity`.
+    #[zbus(property)]
+    async fn selection_name(&self) -> String {
+        self.current_session().selection.name()
+    }
+
+    /// The presets `EnablePreset` knows, as names and lengths in seconds.
+    #[zbus(property)]
+    async fn presets(&self) -> Vec<(String, u64)> {
+        self.current_presets()
+            .into_iter()
+            .map(|preset| {
+                let secs = preset.secs();
+                (preset.name, secs)
+            })
+            .collect()
+    }
+
+    /// Unix time at which the session ends, or -1 when it has no timer.
+    #[zbus(property)]
+    async fn expiry_timestamp(&self) -> i64 {
+        self.current_session().expiry_ts
+    }
+
+    /// Seconds until the session ends, or -1 when it has no timer. Not
+    /// announced through `PropertiesChanged` since it changes every second.
+    /// Unlike `ExpiryTimestamp` it is not thrown off by changes to the
+    /// system time.
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn remaining_seconds(&self) -> i64 {
+        self.remaining_secs().map(|secs| secs as i64).unwrap_or(-1)
+    }
+
+    /// Why the session is being kept awake, empty when caffeine is off.
+    #[zbus(property)]
+    async fn reason(&self) -> String {
+        self.current_reason()
+    }
+
+    /// What the inhibit covers, as idle, suspend, logout and user switch.
+    #[zbus(property)]
+    async fn scope(&self) -> InhibitScope {
+        self.current_scope()
+    }
+
+    /// `Full` or `AllowLock`, following from `Scope`.
+    #[zbus(property)]
+    async fn mode(&self) -> String {
+        self.current_scope().mode().name().to_string()
+    }
+
+    /// How many inhibits other applications hold through `Inhibit`.
+    #[zbus(property)]
+    async fn clients(&self) -> u32 {
+        self.client_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{BackendKind, LogindMode};
+    use crate::clock::FakeClock;
+    use crate::testbus::{self, MockLogind, MockPortal, PortalCall, TIMEOUT};
+    use futures_util::Stream;
+
+    /// Always use the portal, so the tests never probe the system bus.
+    const PORTAL: BackendConfig = BackendConfig {
+        kind: BackendKind::Portal,
+        logind_mode: LogindMode::Block,
+        logind_lid_switch: false,
+    };
+
+    const INFINITY_REASON: &str = "User enabled infinity caffeine mode";
+
+    /// Serve caffeine on its own connection and return a client for it.
+    async fn start_service() -> CaffeineManagerProxy<'static> {
+        start_service_with_clock(Arc::new(MonotonicClock::new())).await
+    }
+
+    async fn start_service_with_clock(clock: Arc<dyn Clock>) -> CaffeineManagerProxy<'static> {
+        start_service_with(SessionConfig::default(), clock, None).await
+    }
+
+    async fn start_service_with(
+        session: SessionConfig,
+        clock: Arc<dyn Clock>,
+        save_path: Option<PathBuf>,
+    ) -> CaffeineManagerProxy<'static> {
+        start_service_on(PORTAL, session, clock, save_path).await
+    }
+
+    async fn start_service_on(
+        backend: BackendConfig,
+        session: SessionConfig,
+        clock: Arc<dyn Clock>,
+        save_path: Option<PathBuf>,
+    ) -> CaffeineManagerProxy<'static> {
+        let conn = testbus::connect().await;
+        let system = testbus::connect().await;
+        let served = serve_with_clock(&conn, Some(system), backend, session, clock, save_path);
+        assert!(served.await.unwrap());
+        let client = testbus::connect().await;
+        CaffeineManagerProxy::new(&client).await.unwrap()
+    }
+
+    async fn next_state(signals: &mut (impl Stream<Item = StateChanged> + Unpin)) -> CaffeineState {
+        let signal = tokio::time::timeout(TIMEOUT, signals.next())
+            .await
+            .expect("no StateChanged signal")
+            .expect("signal stream ended");
+        signal.args().unwrap().state
+    }
+
+    /// Wait for the service to catch up with a resume.
+    async fn wait_for_remaining(caffeine: &CaffeineManagerProxy<'_>, secs: i64) {
+        let caught_up = async {
+            while caffeine.remaining_seconds().await.unwrap() != secs {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(TIMEOUT, caught_up)
+            .await
+            .unwrap_or_else(|_| panic!("remaining time never reached {}s", secs));
+    }
+
+    /// Wait for the session saved at `path` to be `active`, as it is
+    /// written in the background.
+    async fn wait_for_saved(path: &std::path::Path, active: bool) -> SavedSession {
+        let written = async {
+            loop {
+                match persist::load(path) {
+                    Some(saved) if saved.active == active => return saved,
+                    _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        tokio::time::timeout(TIMEOUT, written)
+            .await
+            .unwrap_or_else(|_| panic!("no session with active={} saved", active))
+    }
+
+    /// Wait for `Clients` to be announced as `count`.
+    async fn wait_for_clients(caffeine: &CaffeineManagerProxy<'_>, count: u32) {
+        let mut changes = caffeine.receive_clients_changed().await;
+        let announced = async {
+            while let Some(change) = changes.next().await {
+                if change.get().await.unwrap() == count {
+                    return;
+                }
+            }
+        };
+        tokio::time::timeout(TIMEOUT, announced)
+            .await
+            .unwrap_or_else(|_| panic!("client count never reached {}", count));
+    }
+
+    /// A file to save the session to that no other test uses.
+    fn session_path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "cosmic-caffeine-{}-{}.json",
+            test,
+            std::process::id()
+        ))
+    }
+
+    fn error_name(result: zbus::Result<()>) -> String {
+        match result {
+            Err(zbus::Error::MethodError(name, _, _)) => name.to_string(),
+            other => panic!("expected a D-Bus error, got {:?}", other),
+        }
+    }
+
+    fn inhibit(reason: &str) -> PortalCall {
+        PortalCall::Inhibit {
+            flags: 8,
+            reason: reason.to_string(),
+        }
+    }
+
+    #[test]
+    fn enable_and_disable_hold_and_release_the_portal_inhibit() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let caffeine = start_service().await;
+            let mut signals = caffeine.receive_state_changed().await.unwrap();
+
+            caffeine.enable_indefinitely().await.unwrap();
+            let state = next_state(&mut signals).await;
+            assert!(state.is_active());
+            assert_eq!(state.selection, SelectionKind::Infinity);
+            assert_eq!(portal.wait_for_calls(1).await, [inhibit(INFINITY_REASON)]);
+
+            caffeine.disable().await.unwrap();
+            assert!(!next_state(&mut signals).await.is_active());
+            assert_eq!(
+                portal.wait_for_calls(2).await,
+                [inhibit(INFINITY_REASON), PortalCall::Close]
+            );
+        });
+    }
+
+    #[test]
+    fn timer_expiry_releases_the_inhibit() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let caffeine = start_service_with_clock(clock.clone()).await;
+            let mut signals = caffeine.receive_state_changed().await.unwrap();
+
+            caffeine.enable_for(60).await.unwrap();
+            let state = next_state(&mut signals).await;
+            assert!(state.is_active());
+            assert_eq!(state.expiry_ts, 1_700_000_060);
+
+            clock.advance(Duration::from_secs(59));
+            assert_eq!(caffeine.remaining_seconds().await.unwrap(), 1);
+            clock.advance(Duration::from_secs(1));
+            assert!(!next_state(&mut signals).await.is_active());
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn timer_ignores_wall_clock_jumps() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let caffeine = start_service_with_clock(clock.clone()).await;
+            let mut signals = caffeine.receive_state_changed().await.unwrap();
+
+            caffeine.enable_for(3600).await.unwrap();
+            assert!(next_state(&mut signals).await.is_active());
+
+            // NTP moves the wall clock a day ahead, then back to before the
+            // session started. Neither ends it or changes what is left.
+            clock.set_unix_now(1_700_086_400);
+            clock.advance(Duration::from_secs(600));
+            assert_eq!(caffeine.remaining_seconds().await.unwrap(), 3000);
+            clock.set_unix_now(1_699_990_000);
+            clock.advance(Duration::from_secs(600));
+            assert_eq!(caffeine.remaining_seconds().await.unwrap(), 2400);
+            assert!(caffeine.get_state().await.unwrap().is_active());
+            assert_eq!(portal.calls().len(), 1);
+
+            clock.advance(Duration::from_secs(2400));
+            assert!(!next_state(&mut signals).await.is_active());
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn auto_takes_the_logind_sleep_lock_next_to_the_portal() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let logind = MockLogind::start().await;
+            let backend = BackendConfig {
+                kind: BackendKind::Auto,
+                ..PORTAL
+            };
+            let session = SessionConfig {
+                scope: InhibitScope {
+                    suspend: true,
+                    ..InhibitScope::default()
+                },
+                ..SessionConfig::default()
+            };
+            let clock = Arc::new(MonotonicClock::new());
+            let caffeine = start_service_on(backend, session, clock, None).await;
+
+            caffeine.enable_indefinitely().await.unwrap();
+            assert!(matches!(
+                portal.wait_for_calls(1).await[0],
+                PortalCall::Inhibit { .. }
+            ));
+            assert_eq!(logind.held_locks(), vec!["sleep".to_string()]);
+
+            caffeine.disable().await.unwrap();
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+            assert!(logind.held_locks().is_empty());
+        });
+    }
+
+    #[test]
+    fn suspended_time_counts_towards_the_timer() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let logind = MockLogind::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let caffeine = start_service_with_clock(clock.clone()).await;
+            let mut signals = caffeine.receive_state_changed().await.unwrap();
+
+            caffeine.enable_for(3600).await.unwrap();
+            assert_eq!(next_state(&mut signals).await.expiry_ts, 1_700_003_600);
+
+            logind.suspend().await;
+            clock.suspend(Duration::from_secs(1800));
+            logind.resume().await;
+            wait_for_remaining(&caffeine, 1800).await;
+            assert_eq!(caffeine.expiry_timestamp().await.unwrap(), 1_700_003_600);
+
+            // Sleeping through the rest ends the session on resume.
+            logind.suspend().await;
+            clock.suspend(Duration::from_secs(7200));
+            logind.resume().await;
+            assert!(!next_state(&mut signals).await.is_active());
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn paused_timer_resumes_where_it_left_off() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let logind = MockLogind::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let session = SessionConfig {
+                suspend_policy: SuspendPolicy::Pause,
+                ..SessionConfig::default()
+            };
+            let caffeine = start_service_with(session, clock.clone(), None).await;
+            let mut signals = caffeine.receive_state_changed().await.unwrap();
+
+            caffeine.enable_for(3600).await.unwrap();
+            assert!(next_state(&mut signals).await.is_active());
+
+            clock.advance(Duration::from_secs(600));
+            logind.suspend().await;
+            clock.suspend(Duration::from_secs(7200));
+            logind.resume().await;
+            let state = next_state(&mut signals).await;
+            assert!(state.is_active());
+            assert_eq!(state.expiry_ts, 1_700_010_800);
+            assert_eq!(caffeine.remaining_seconds().await.unwrap(), 3000);
+
+            clock.advance(Duration::from_secs(3000));
+            assert!(!next_state(&mut signals).await.is_active());
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn paused_timer_still_ends_at_its_time_of_day() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let logind = MockLogind::start().await;
+            let now = 1_700_000_000;
+            let clock = Arc::new(FakeClock::new(now));
+            let session = SessionConfig {
+                suspend_policy: SuspendPolicy::Pause,
+                ..SessionConfig::default()
+            };
+            let caffeine = start_service_with(session, clock.clone(), None).await;
+
+            // Some hours away, whatever the zone the tests run in.
+            let time = localtime::time_of_day(now + 4 * 3600).unwrap();
+            caffeine.enable_until_time(&time.to_string()).await.unwrap();
+            let expiry_ts = caffeine.expiry_timestamp().await.unwrap();
+            let remaining = caffeine.remaining_seconds().await.unwrap();
+
+            logind.suspend().await;
+            clock.suspend(Duration::from_secs(3600));
+            logind.resume().await;
+            wait_for_remaining(&caffeine, remaining - 3600).await;
+            assert_eq!(caffeine.expiry_timestamp().await.unwrap(), expiry_ts);
+
+            caffeine.disable().await.unwrap();
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn session_is_saved_as_it_changes() {
+        testbus::run(async {
+            let _portal = MockPortal::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let path = session_path("saved");
+            let session = SessionConfig::default();
+            let caffeine = start_service_with(session, clock, Some(path.clone())).await;
+
+            caffeine.enable_for(3600).await.unwrap();
+            let saved = wait_for_saved(&path, true).await;
+            assert!(saved.active);
+            assert_eq!(saved.selection, TimerSelection::Timer(3600));
+            assert_eq!(saved.expiry_ts, 1_700_003_600);
+            assert_eq!(saved.reason, "User enabled caffeine for 1h 0m");
+
+            caffeine.disable().await.unwrap();
+            wait_for_saved(&path, false).await;
+            std::fs::remove_file(&path).unwrap();
+        });
+    }
+
+    #[test]
+    fn saved_timer_resumes_on_startup() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let path = session_path("resume");
+            let scope = InhibitScope {
+                suspend: true,
+                ..InhibitScope::default()
+            };
+            let saved = SavedSession {
+                active: true,
+                selection: TimerSelection::Timer(7200),
+                expiry_ts: 1_700_000_600,
+                reason: "Rendering".to_string(),
+                scope,
+                login: None,
+            };
+            persist::save(&path, &saved);
+
+            let session = SessionConfig::default();
+            let caffeine = start_service_with(session, clock.clone(), Some(path.clone())).await;
+            let mut signals = caffeine.receive_state_changed().await.unwrap();
+            let state = caffeine.get_state().await.unwrap();
+            assert!(state.is_active());
+            assert_eq!(state.selection, SelectionKind::TwoHours);
+            assert_eq!(state.expiry_ts, 1_700_000_600);
+            assert_eq!(caffeine.scope().await.unwrap(), scope);
+            assert_eq!(
+                portal.calls(),
+                [PortalCall::Inhibit {
+                    flags: 12,
+                    reason: "Rendering".to_string(),
+                }]
+            );
+
+            clock.advance(Duration::from_secs(600));
+            assert!(!next_state(&mut signals).await.is_active());
+            wait_for_saved(&path, false).await;
+            std::fs::remove_file(&path).unwrap();
+        });
+    }
+
+    #[test]
+    fn invalid_and_repeated_requests_are_rejected() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let caffeine = start_service().await;
+
+            assert_eq!(
+                error_name(caffeine.enable_for(0).await),
+                "com.github.oussama_berchi.cosmic_caffeine.Error.InvalidArgs"
+            );
+            assert_eq!(
+                error_name(caffeine.set_mode("Nope").await),
+                "com.github.oussama_berchi.cosmic_caffeine.Error.InvalidArgs"
+            );
+
+            caffeine.enable_indefinitely().await.unwrap();
+            assert_eq!(
+                error_name(caffeine.enable_for(60).await),
+                "com.github.oussama_berchi.cosmic_caffeine.Error.AlreadyActive"
+            );
+            assert_eq!(portal.calls(), [inhibit(INFINITY_REASON)]);
+        });
+    }
+
+    #[test]
+    fn missing_portal_is_reported() {
+        testbus::run(async {
+            let caffeine = start_service().await;
+
+            assert_eq!(
+                error_name(caffeine.enable_indefinitely().await),
+                "com.github.oussama_berchi.cosmic_caffeine.Error.PortalUnavailable"
+            );
+            assert!(!caffeine.get_state().await.unwrap().is_active());
+        });
+    }
+
+    #[test]
+    fn denied_inhibit_is_reported() {
+        testbus::run(async {
+            let portal = MockPortal::denying().await;
+            let caffeine = start_service().await;
+
+            assert_eq!(
+                error_name(caffeine.enable_indefinitely().await),
+                "com.github.oussama_berchi.cosmic_caffeine.Error.InhibitDenied"
+            );
+            assert!(!caffeine.get_state().await.unwrap().is_active());
+            assert_eq!(portal.calls(), [inhibit(INFINITY_REASON)]);
+        });
+    }
+
+    #[test]
+    fn scope_change_retakes_the_inhibit() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let caffeine = start_service().await;
+
+            caffeine.enable_indefinitely().await.unwrap();
+            caffeine.set_mode("AllowLock").await.unwrap();
+
+            assert_eq!(
+                portal.wait_for_calls(3).await,
+                [
+                    inhibit(INFINITY_REASON),
+                    PortalCall::Inhibit {
+                        flags: 4,
+                        reason: INFINITY_REASON.to_string(),
+                    },
+                    PortalCall::Close,
+                ]
+            );
+            assert_eq!(
+                caffeine.mode().await.unwrap(),
+                InhibitMode::AllowLock.name()
+            );
+        });
+    }
+
+    #[test]
+    fn default_scope_applies_from_the_start() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let session = SessionConfig {
+                scope: InhibitScope::default().with_mode(InhibitMode::AllowLock),
+                ..SessionConfig::default()
+            };
+            let clock = Arc::new(MonotonicClock::new());
+            let caffeine = start_service_with(session, clock, None).await;
+            assert_eq!(
+                caffeine.mode().await.unwrap(),
+                InhibitMode::AllowLock.name()
+            );
+
+            caffeine.enable_indefinitely().await.unwrap();
+            assert_eq!(
+                portal.calls(),
+                [PortalCall::Inhibit {
+                    flags: 4,
+                    reason: INFINITY_REASON.to_string(),
+                }]
+            );
+        });
+    }
+
+    #[test]
+    fn preset_starts_by_name() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let session = SessionConfig {
+                presets: vec![
+                    TimerPreset::new("Stand-up", 15),
+                    TimerPreset::new("Movie", 150),
+                ],
+                ..SessionConfig::default()
+            };
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let caffeine = start_service_with(session, clock, None).await;
+            assert_eq!(
+                caffeine.presets().await.unwrap(),
+                [("Stand-up".to_string(), 900), ("Movie".to_string(), 9000)]
+            );
+
+            let err = caffeine.enable_preset("Deploy").await.unwrap_err();
+            assert!(
+                err.to_string().contains("No preset named Deploy"),
+                "{}",
+                err
+            );
+
+            caffeine.enable_preset("movie").await.unwrap();
+            let state = caffeine.get_state().await.unwrap();
+            assert_eq!(state.selection, SelectionKind::Manual);
+            assert_eq!(state.expiry_ts, 1_700_009_000);
+            assert_eq!(caffeine.selection().await.unwrap(), "Manual");
+            assert_eq!(caffeine.selection_name().await.unwrap(), "Timer:9000");
+            assert_eq!(portal.calls(), [inhibit("User enabled the Movie preset")]);
+
+            caffeine.disable().await.unwrap();
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn timer_length_is_parsed_like_the_manual_field() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let caffeine = start_service_with_clock(clock).await;
+
+            let err = caffeine.enable_for_duration("soon").await.unwrap_err();
+            assert!(err.to_string().contains("Error.InvalidArgs"), "{}", err);
+            assert!(err.to_string().contains("Not a duration"), "{}", err);
+
+            caffeine.enable_for_duration("1h30m").await.unwrap();
+            assert_eq!(caffeine.get_state().await.unwrap().expiry_ts, 1_700_005_400);
+            assert_eq!(caffeine.selection_name().await.unwrap(), "Timer:5400");
+
+            caffeine.disable().await.unwrap();
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn timer_runs_until_a_time_of_day() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let caffeine = start_service_with_clock(clock).await;
+
+            let err = caffeine.enable_until_time("25:00").await.unwrap_err();
+            assert!(err.to_string().contains("expected HH:MM"), "{}", err);
+
+            caffeine.enable_until_time("17:30").await.unwrap();
+            let state = caffeine.get_state().await.unwrap();
+            assert_eq!(state.selection, SelectionKind::Manual);
+            assert!((1_700_000_001..=1_700_090_000).contains(&state.expiry_ts));
+            assert_eq!(caffeine.selection_name().await.unwrap(), "Until:17:30");
+            assert_eq!(
+                portal.calls(),
+                [inhibit("User enabled caffeine until 17:30")]
+            );
+
+            caffeine.disable().await.unwrap();
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn timer_is_extended_without_a_new_inhibit() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let clock = Arc::new(FakeClock::new(1_700_000_000));
+            let caffeine = start_service_with_clock(clock.clone()).await;
+            let mut signals = caffeine.receive_state_changed().await.unwrap();
+
+            let err = caffeine.extend(900).await.unwrap_err();
+            assert!(err.to_string().contains("Error.NoTimer"), "{}", err);
+
+            caffeine.enable_for(1800).await.unwrap();
+            assert!(next_state(&mut signals).await.is_active());
+
+            caffeine.extend(3600).await.unwrap();
+            assert_eq!(next_state(&mut signals).await.expiry_ts, 1_700_005_400);
+            assert_eq!(caffeine.selection_name().await.unwrap(), "Timer:5400");
+            caffeine.extend(-900).await.unwrap();
+            assert_eq!(next_state(&mut signals).await.expiry_ts, 1_700_004_500);
+            assert_eq!(caffeine.selection_name().await.unwrap(), "Timer:4500");
+            assert_eq!(caffeine.remaining_seconds().await.unwrap(), 4500);
+
+            clock.advance(Duration::from_secs(4000));
+            let err = caffeine.extend(-900).await.unwrap_err();
+            assert!(err.to_string().contains("only 8m is left"), "{}", err);
+
+            // The old deadline passes without ending the session.
+            clock.advance(Duration::from_secs(499));
+            assert_eq!(caffeine.remaining_seconds().await.unwrap(), 1);
+            assert_eq!(portal.calls(), [inhibit("User enabled caffeine for 30m")]);
+
+            clock.advance(Duration::from_secs(1));
+            assert!(!next_state(&mut signals).await.is_active());
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn client_inhibit_ends_when_the_client_leaves() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let caffeine = start_service().await;
+
+            let client_conn = testbus::connect().await;
+            let client = CaffeineManagerProxy::new(&client_conn).await.unwrap();
+            assert!(client.inhibit("backup", "Copying files").await.unwrap() > 0);
+            wait_for_clients(&caffeine, 1).await;
+            assert!(!caffeine.active().await.unwrap());
+            assert_eq!(portal.calls(), [inhibit("backup: Copying files")]);
+
+            client_conn.close().await.unwrap();
+            wait_for_clients(&caffeine, 0).await;
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn trigger_process_keeps_the_session_awake() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            // The test binary itself is the trigger.
+            let name = process::process_name(std::process::id()).unwrap();
+            let session = SessionConfig {
+                trigger_processes: vec![name.clone()],
+                ..SessionConfig::default()
+            };
+            let conn = testbus::connect().await;
+            let clock = Arc::new(MonotonicClock::new());
+            let served = serve_with_clock(&conn, None, PORTAL, session, clock, None);
+            assert!(served.await.unwrap());
+            let client = testbus::connect().await;
+            let caffeine = CaffeineManagerProxy::new(&client).await.unwrap();
+
+            wait_for_clients(&caffeine, 1).await;
+            assert!(!caffeine.active().await.unwrap());
+            let reason = format!("{} running", name);
+            assert_eq!(portal.wait_for_calls(1).await, [inhibit(&reason)]);
+
+            set_triggers(&conn, Vec::new()).await.unwrap();
+            wait_for_clients(&caffeine, 0).await;
+            assert_eq!(portal.wait_for_calls(2).await[1], PortalCall::Close);
+        });
+    }
+
+    #[test]
+    fn scope_is_set_only_where_the_service_is_served() {
+        testbus::run(async {
+            let conn = testbus::connect().await;
+            let clock = Arc::new(MonotonicClock::new());
+            let session = SessionConfig::default();
+            let served = serve_with_clock(&conn, None, PORTAL, session, clock, None);
+            assert!(served.await.unwrap());
+            let client = testbus::connect().await;
+            let caffeine = CaffeineManagerProxy::builder(&client)
+                .cache_properties(zbus::proxy::CacheProperties::No)
+                .build()
+                .await
+                .unwrap();
+            let suspend = InhibitScope {
+                idle: false,
+                suspend: true,
+                logout: false,
+                user_switch: false,
+            };
+
+            set_scope(&client, suspend).await.unwrap();
+            assert_eq!(caffeine.scope().await.unwrap(), InhibitScope::default());
+
+            set_scope(&conn, suspend).await.unwrap();
+            assert_eq!(caffeine.scope().await.unwrap(), suspend);
+        });
+    }
+
+    #[test]
+    fn trigger_is_dropped_when_the_inhibit_cannot_be_restored() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let name = process::process_name(std::process::id()).unwrap();
+            let session = SessionConfig {
+                trigger_processes: vec![name.clone()],
+                ..SessionConfig::default()
+            };
+            let conn = testbus::connect().await;
+            let clock = Arc::new(MonotonicClock::new());
+            let served = serve_with_clock(&conn, None, PORTAL, session, clock, None);
+            assert!(served.await.unwrap());
+            let client = testbus::connect().await;
+            let caffeine = CaffeineManagerProxy::new(&client).await.unwrap();
+            wait_for_clients(&caffeine, 1).await;
+
+            let other_conn = testbus::connect().await;
+            let other = CaffeineManagerProxy::new(&other_conn).await.unwrap();
+            let cookie = other.inhibit("backup", "Copying files").await.unwrap();
+            wait_for_clients(&caffeine, 2).await;
+            portal.stop().await;
+
+            let restarted = MockPortal::denying().await;
+            wait_for_clients(&caffeine, 0).await;
+            assert!(!caffeine.active().await.unwrap());
+            assert!(other.un_inhibit(cookie).await.is_err());
+
+            // The trigger tries again on the next poll.
+            let reason = format!("{} running", name);
+            let retried = restarted.wait_for_calls(2).await;
+            assert!(
+                retried.iter().all(|call| *call == inhibit(&reason)),
+                "{:?}",
+                retried
+            );
+            set_triggers(&conn, Vec::new()).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn inhibit_is_restored_after_portal_restart() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let caffeine = start_service().await;
+
+            caffeine.enable_indefinitely().await.unwrap();
+            portal.wait_for_calls(1).await;
+            portal.stop().await;
+
+            let restarted = MockPortal::start().await;
+            assert_eq!(
+                restarted.wait_for_calls(1).await,
+                [inhibit(INFINITY_REASON)]
+            );
+            assert!(caffeine.get_state().await.unwrap().is_active());
+        });
+    }
+
+    #[test]
+    fn session_ends_when_the_inhibit_cannot_be_restored() {
+        testbus::run(async {
+            let portal = MockPortal::start().await;
+            let caffeine = start_service().await;
+            let mut signals = caffeine.receive_state_changed().await.unwrap();
+
+            caffeine.enable_indefinitely().await.unwrap();
+            assert!(next_state(&mut signals).await.is_active());
+            portal.stop().await;
+
+            let restarted = MockPortal::denying().await;
+            assert!(!next_state(&mut signals).await.is_active());
+            assert!(!caffeine.active().await.unwrap());
+            assert_eq!(restarted.calls(), [inhibit(INFINITY_REASON)]);
+        });
     }
 }
@@ -0,0 +1,487 @@
+use cosmic::cosmic_config;
+use cosmic::iced::{window::Id, Alignment, Length};
+use cosmic::prelude::*;
+use cosmic::theme;
+use cosmic::widget;
+use tracing::error;
+
+use crate::backend::{BackendKind, LogindMode};
+use crate::config::{Config, DefaultTimer};
+use crate::duration::{self, DurationError};
+use crate::fl;
+use crate::localtime::TimeOfDay;
+use crate::notify::NotificationPolicy;
+use crate::state::{InhibitScope, SuspendPolicy, TimerPreset};
+
+/// A page of the settings window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    Presets,
+    DefaultTimer,
+    Notifications,
+    Scope,
+    Sessions,
+    Triggers,
+    Backend,
+}
+
+impl Page {
+    const ALL: [Page; 7] = [
+        Page::Presets,
+        Page::DefaultTimer,
+        Page::Notifications,
+        Page::Scope,
+        Page::Sessions,
+        Page::Triggers,
+        Page::Backend,
+    ];
+
+    fn title(&self) -> String {
+        match self {
+            Page::Presets => fl!("settings-presets"),
+            Page::DefaultTimer => fl!("settings-default-timer"),
+            Page::Notifications => fl!("settings-notifications"),
+            Page::Scope => fl!("settings-scope"),
+            Page::Sessions => fl!("settings-restore"),
+            Page::Triggers => fl!("settings-triggers"),
+            Page::Backend => fl!("settings-backend"),
+        }
+    }
+}
+
+/// Something done in the settings window.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Page(Page),
+    PresetName(String),
+    PresetInput(String),
+    AddPreset,
+    RemovePreset(usize),
+    ManualMinutes(String),
+    MorningTime(String),
+    DefaultTimer(DefaultTimer),
+    Scope(InhibitScope),
+    Notifications(NotificationPolicy),
+    SuspendPolicy(SuspendPolicy),
+    RestoreInfinity(bool),
+    TriggerInput(String),
+    AddTrigger,
+    RemoveTrigger(usize),
+    Backend(BackendKind),
+    LogindMode(LogindMode),
+    LogindLidSwitch(bool),
+}
+
+/// The open settings window and what has been typed into it.
+pub struct SettingsWindow {
+    pub id: Id,
+    page: Page,
+    preset_name: String,
+    preset_input: String,
+    manual_input: String,
+    morning_input: String,
+    trigger_input: String,
+}
+
+impl SettingsWindow {
+    pub fn new(id: Id, config: &Config) -> Self {
+        Self {
+            id,
+            page: Page::Presets,
+            preset_name: String::new(),
+            preset_input: String::new(),
+            manual_input: config.manual_minutes.to_string(),
+            morning_input: config.morning_time.clone(),
+            trigger_input: String::new(),
+        }
+    }
+
+    /// Apply `change` to `config`, saving it through `handler`.
+    pub fn update(
+        &mut self,
+        config: &mut Config,
+        handler: Option<&cosmic_config::Config>,
+        change: Change,
+    ) {
+        let Some(handler) = handler else {
+            error!("No config to save settings to");
+            return;
+        };
+
+        let saved = match change {
+            Change::Page(page) => {
+                self.page = page;
+                Ok(false)
+            }
+            Change::PresetName(value) => {
+                self.preset_name = value;
+                Ok(false)
+            }
+            Change::PresetInput(value) => {
+                if value.chars().all(|c| c.is_ascii_digit()) {
+                    self.preset_input = value;
+                }
+                Ok(false)
+            }
+            Change::AddPreset => {
+                let name = self.preset_name.trim();
+                match self.preset_input.parse::<u32>() {
+                    Ok(mins)
+                        if mins > 0
+                            && !name.is_empty()
+                            && TimerPreset::find(&config.presets, name).is_none() =>
+                    {
+                        let mut presets = config.presets.clone();
+                        presets.push(TimerPreset::new(name, mins));
+                        presets.sort_by_key(|preset| preset.minutes);
+                        self.preset_name.clear();
+                        self.preset_input.clear();
+                        config.set_presets(handler, presets)
+                    }
+                    _ => Ok(false),
+                }
+            }
+            Change::RemovePreset(index) => {
+                let mut presets = config.presets.clone();
+                if index < presets.len() {
+                    presets.remove(index);
+                }
+                config.set_presets(handler, presets)
+            }
+            Change::ManualMinutes(value) => {
+                self.manual_input = value;
+                match manual_minutes(&self.manual_input) {
+                    Ok(mins) => config.set_manual_minutes(handler, mins),
+                    Err(_) => Ok(false),
+                }
+            }
+            Change::MorningTime(value) => {
+                self.morning_input = value;
+                match TimeOfDay::parse(&self.morning_input) {
+                    Some(time) => config.set_morning_time(handler, time.to_string()),
+                    None => Ok(false),
+                }
+            }
+            Change::DefaultTimer(timer) => config.set_default_timer(handler, timer),
+            Change::Scope(scope) if scope.is_empty() => Ok(false),
+            Change::Scope(scope) => config.set_default_scope(handler, scope),
+            Change::Notifications(policy) => config.set_notifications(handler, policy),
+            Change::SuspendPolicy(policy) => config.set_suspend_policy(handler, policy),
+            Change::RestoreInfinity(restore) => config.set_restore_infinity(handler, restore),
+            Change::TriggerInput(value) => {
+                self.trigger_input = value;
+                Ok(false)
+            }
+            Change::AddTrigger => {
+                let name = self.trigger_input.trim().to_string();
+                if name.is_empty() || config.trigger_processes.contains(&name) {
+                    Ok(false)
+                } else {
+                    self.trigger_input.clear();
+                    let mut triggers = config.trigger_processes.clone();
+                    triggers.push(name);
+                    config.set_trigger_processes(handler, triggers)
+                }
+            }
+            Change::RemoveTrigger(index) => {
+                let mut triggers = config.trigger_processes.clone();
+                if index < triggers.len() {
+                    triggers.remove(index);
+                }
+                config.set_trigger_processes(handler, triggers)
+            }
+            Change::Backend(kind) => config.set_backend(handler, kind),
+            Change::LogindMode(mode) => config.set_logind_mode(handler, mode),
+            Change::LogindLidSwitch(lid) => config.set_logind_lid_switch(handler, lid),
+        };
+        if let Err(e) = saved {
+            error!("Failed to save settings: {}", e);
+        }
+    }
+
+    pub fn view<'a>(&'a self, config: &'a Config) -> Element<'a, Change> {
+        let spacing = theme::active().cosmic().spacing;
+
+        let mut nav = widget::column().spacing(spacing.space_xxs);
+        for page in Page::ALL {
+            nav = nav.push(
+                widget::button::text(page.title())
+                    .selected(page == self.page)
+                    .on_press(Change::Page(page))
+                    .width(Length::Fill),
+            );
+        }
+
+        let page = match self.page {
+            Page::Presets => self.presets_page(config),
+            Page::DefaultTimer => default_timer_page(config),
+            Page::Notifications => notifications_page(config),
+            Page::Scope => scope_page(config),
+            Page::Sessions => sessions_page(config),
+            Page::Triggers => self.triggers_page(config),
+            Page::Backend => backend_page(config),
+        };
+
+        let content = widget::row()
+            .push(widget::container(nav).width(Length::Fixed(180.0)))
+            .push(widget::scrollable(page).width(Length::Fill))
+            .spacing(spacing.space_l)
+            .padding(spacing.space_m);
+
+        widget::layer_container(content)
+            .layer(cosmic::cosmic_theme::Layer::Background)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    fn presets_page<'a>(&'a self, config: &'a Config) -> Element<'a, Change> {
+        let mut presets = widget::settings::section().title(fl!("settings-presets"));
+        for (index, preset) in config.presets.iter().enumerate() {
+            presets = presets.add(
+                widget::settings::item::builder(preset.name.as_str())
+                    .description(crate::app::preset_label(preset.minutes))
+                    .control(
+                        widget::button::icon(widget::icon::from_name("list-remove-symbolic"))
+                            .on_press(Change::RemovePreset(index)),
+                    ),
+            );
+        }
+        presets = presets.add(widget::settings::item(
+            fl!("settings-add-preset"),
+            widget::row()
+                .push(
+                    widget::text_input(fl!("settings-preset-name"), &self.preset_name)
+                        .on_input(Change::PresetName)
+                        .width(Length::Fixed(140.0)),
+                )
+                .push(
+                    widget::text_input(fl!("settings-minutes"), &self.preset_input)
+                        .on_input(Change::PresetInput)
+                        .width(Length::Fixed(80.0)),
+                )
+                .push(widget::button::standard(fl!("settings-add")).on_press(Change::AddPreset))
+                .spacing(theme::active().cosmic().spacing.space_xs)
+                .align_y(Alignment::Center),
+        ));
+
+        let mut manual = widget::settings::section().add(widget::settings::item(
+            fl!("settings-manual-minutes"),
+            widget::text_input(fl!("manual-placeholder"), &self.manual_input)
+                .on_input(Change::ManualMinutes)
+                .width(Length::Fixed(120.0)),
+        ));
+        if let Err(e) = manual_minutes(&self.manual_input) {
+            manual = manual.add(widget::text::caption(e.to_string()));
+        }
+        let manual = manual.add(widget::settings::item(
+            fl!("settings-morning-time"),
+            widget::text_input("08:00", &self.morning_input)
+                .on_input(Change::MorningTime)
+                .width(Length::Fixed(80.0)),
+        ));
+
+        widget::settings::view_column(vec![presets.into(), manual.into()]).into()
+    }
+
+    fn triggers_page<'a>(&'a self, config: &'a Config) -> Element<'a, Change> {
+        let mut triggers = widget::settings::section()
+            .title(fl!("settings-trigger-processes"))
+            .add(widget::text::caption(fl!(
+                "settings-trigger-processes-description"
+            )));
+        for (index, name) in config.trigger_processes.iter().enumerate() {
+            triggers = triggers.add(widget::settings::item(
+                name.as_str(),
+                widget::button::icon(widget::icon::from_name("list-remove-symbolic"))
+                    .on_press(Change::RemoveTrigger(index)),
+            ));
+        }
+        triggers = triggers.add(widget::settings::item(
+            fl!("settings-add-process"),
+            widget::row()
+                .push(
+                    widget::text_input(fl!("settings-process-name"), &self.trigger_input)
+                        .on_input(Change::TriggerInput)
+                        .width(Length::Fixed(160.0)),
+                )
+                .push(widget::button::standard(fl!("settings-add")).on_press(Change::AddTrigger))
+                .spacing(theme::active().cosmic().spacing.space_xs)
+                .align_y(Alignment::Center),
+        ));
+
+        widget::settings::view_column(vec![triggers.into()]).into()
+    }
+}
+
+fn default_timer_page(config: &Config) -> Element<'_, Change> {
+    let mut timers = vec![(fl!("timer-infinity"), DefaultTimer::Infinity)];
+    timers.extend(config.presets.iter().map(|preset| {
+        (
+            preset.name.clone(),
+            DefaultTimer::Preset(preset.name.clone()),
+        )
+    }));
+    timers.push((fl!("timer-manual"), DefaultTimer::Manual));
+    timers.push((fl!("timer-until"), DefaultTimer::Until));
+    timers.push((
+        fl!("timer-morning", time = config.morning_time().to_string()),
+        DefaultTimer::Morning,
+    ));
+
+    // Nothing is picked once the default preset is gone, as the popup then
+    // starts on infinity.
+    let picked = timers
+        .iter()
+        .position(|(_, timer)| *timer == config.default_timer);
+    let mut section = widget::settings::section().title(fl!("settings-default-timer"));
+    for (index, (label, timer)) in timers.into_iter().enumerate() {
+        section = section.add(widget::radio(
+            widget::text::body(label),
+            index,
+            picked,
+            move |_| Change::DefaultTimer(timer.clone()),
+        ));
+    }
+
+    widget::settings::view_column(vec![section.into()]).into()
+}
+
+fn notifications_page(config: &Config) -> Element<'_, Change> {
+    let option = |label: String, policy: NotificationPolicy| {
+        widget::radio(
+            widget::text::body(label),
+            policy,
+            Some(config.notifications),
+            Change::Notifications,
+        )
+    };
+    let section = widget::settings::section()
+        .title(fl!("settings-notifications"))
+        .add(option(fl!("settings-notify-all"), NotificationPolicy::All))
+        .add(option(
+            fl!("settings-notify-important"),
+            NotificationPolicy::Important,
+        ))
+        .add(option(
+            fl!("settings-notify-errors"),
+            NotificationPolicy::ErrorsOnly,
+        ));
+
+    widget::settings::view_column(vec![section.into()]).into()
+}
+
+fn sessions_page(config: &Config) -> Element<'_, Change> {
+    let section = widget::settings::section()
+        .title(fl!("settings-restore"))
+        .add(
+            widget::settings::item::builder(fl!("settings-restore-infinity"))
+                .toggler(config.restore_infinity, Change::RestoreInfinity),
+        )
+        .add(widget::settings::item(
+            fl!("settings-suspend-policy"),
+            widget::column()
+                .push(widget::radio(
+                    widget::text::body(fl!("settings-suspend-count")),
+                    SuspendPolicy::Count,
+                    Some(config.suspend_policy),
+                    Change::SuspendPolicy,
+                ))
+                .push(widget::radio(
+                    widget::text::body(fl!("settings-suspend-pause")),
+                    SuspendPolicy::Pause,
+                    Some(config.suspend_policy),
+                    Change::SuspendPolicy,
+                )),
+        ));
+
+    widget::settings::view_column(vec![section.into()]).into()
+}
+
+fn scope_page(config: &Config) -> Element<'_, Change> {
+    let scope = config.default_scope;
+    let section = widget::settings::section()
+        .title(fl!("scope-heading"))
+        .add(
+            widget::settings::item::builder(fl!("scope-idle")).toggler(scope.idle, move |idle| {
+                Change::Scope(InhibitScope { idle, ..scope })
+            }),
+        )
+        .add(
+            widget::settings::item::builder(fl!("scope-suspend"))
+                .toggler(scope.suspend, move |suspend| {
+                    Change::Scope(InhibitScope { suspend, ..scope })
+                }),
+        )
+        .add(
+            widget::settings::item::builder(fl!("scope-logout"))
+                .toggler(scope.logout, move |logout| {
+                    Change::Scope(InhibitScope { logout, ..scope })
+                }),
+        )
+        .add(
+            widget::settings::item::builder(fl!("scope-user-switch")).toggler(
+                scope.user_switch,
+                move |user_switch| {
+                    Change::Scope(InhibitScope {
+                        user_switch,
+                        ..scope
+                    })
+                },
+            ),
+        );
+
+    widget::settings::view_column(vec![section.into()]).into()
+}
+
+/// The manual field's starting value as typed in the settings, in the same
+/// forms as the popup's field, rounded up to whole minutes.
+fn manual_minutes(input: &str) -> Result<u32, DurationError> {
+    let secs = duration::parse(input)?;
+    u32::try_from(secs.div_ceil(60)).map_err(|_| DurationError::TooLong)
+}
+
+fn backend_page(config: &Config) -> Element<'_, Change> {
+    let mut backends = widget::settings::section()
+        .title(fl!("settings-backend"))
+        .add(widget::text::caption(fl!("settings-backend-restart")));
+    for kind in [
+        BackendKind::Auto,
+        BackendKind::Portal,
+        BackendKind::GnomeSession,
+        BackendKind::ScreenSaver,
+        BackendKind::Logind,
+    ] {
+        backends = backends.add(widget::radio(
+            widget::text::body(kind.label()),
+            kind,
+            Some(config.backend),
+            Change::Backend,
+        ));
+    }
+
+    let logind = widget::settings::section()
+        .title(fl!("settings-logind"))
+        .add(widget::settings::item(
+            fl!("settings-logind-mode"),
+            widget::column()
+                .push(widget::radio(
+                    widget::text::body(fl!("settings-logind-block")),
+                    LogindMode::Block,
+                    Some(config.logind_mode),
+                    Change::LogindMode,
+                ))
+                .push(widget::radio(
+                    widget::text::body(fl!("settings-logind-delay")),
+                    LogindMode::Delay,
+                    Some(config.logind_mode),
+                    Change::LogindMode,
+                )),
+        ))
+        .add(
+            widget::settings::item::builder(fl!("settings-logind-lid-switch"))
+                .toggler(config.logind_lid_switch, Change::LogindLidSwitch),
+        );
+
+    widget::settings::view_column(vec![backends.into(), logind.into()]).into()
+}
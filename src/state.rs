@@ -1,44 +1,283 @@
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
-use zbus::zvariant::Type;
+use zbus::zvariant::{OwnedValue, Type, Value};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Type)]
+use crate::localtime::TimeOfDay;
+
+/// The kind of session that is running. Serialized as its `name()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
 pub enum TimerSelection {
+    #[default]
+    Infinity,
+    /// A timer lasting this many seconds.
+    Timer(u64),
+    /// Until the local clock next reads this time.
+    Until(TimeOfDay),
+    WhileProcess,
+}
+
+impl TimerSelection {
+    pub fn label(&self) -> String {
+        match self {
+            TimerSelection::Infinity => "Infinity".to_string(),
+            TimerSelection::Timer(secs) => format!("{} Timer", format_duration(*secs)),
+            TimerSelection::Until(time) => format!("Until {}", time),
+            TimerSelection::WhileProcess => "While Process".to_string(),
+        }
+    }
+
+    /// `label()`, but a timer as long as one of `presets`, given as names
+    /// and lengths in seconds, is called by that preset's name.
+    pub fn label_among<'a>(&self, presets: impl IntoIterator<Item = (&'a str, u64)>) -> String {
+        match self {
+            TimerSelection::Timer(secs) => presets
+                .into_iter()
+                .find(|(_, length)| length == secs)
+                .map(|(name, _)| name.to_string())
+                .unwrap_or_else(|| self.label()),
+            _ => self.label(),
+        }
+    }
+
+    /// Stable identifier used by the `SelectionName` property, e.g.
+    /// `Infinity`, `Timer:5400` or `Until:17:30`.
+    pub fn name(&self) -> String {
+        match self {
+            TimerSelection::Infinity => "Infinity".to_string(),
+            TimerSelection::Timer(secs) => format!("Timer:{}", secs),
+            TimerSelection::Until(time) => format!("Until:{}", time),
+            TimerSelection::WhileProcess => "WhileProcess".to_string(),
+        }
+    }
+
+    /// Read back a `name()`. `OneHour` and `TwoHours`, as saved by older
+    /// versions, are read as the timers they were.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Infinity" => Some(TimerSelection::Infinity),
+            "WhileProcess" => Some(TimerSelection::WhileProcess),
+            "OneHour" => Some(TimerSelection::Timer(3600)),
+            "TwoHours" => Some(TimerSelection::Timer(7200)),
+            _ => match name.split_once(':')? {
+                ("Timer", secs) => secs.parse().ok().map(TimerSelection::Timer),
+                ("Until", time) => TimeOfDay::parse(time).map(TimerSelection::Until),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<TimerSelection> for String {
+    fn from(selection: TimerSelection) -> Self {
+        selection.name()
+    }
+}
+
+impl TryFrom<String> for TimerSelection {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::from_name(&name).ok_or_else(|| format!("unknown timer selection {:?}", name))
+    }
+}
+
+/// The selection as `GetState`, `StateChanged` and the `Selection` property
+/// report it, unchanged since timers could only last one or two hours so
+/// existing clients keep working. Any other timer is `Manual`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Type)]
+pub enum SelectionKind {
     #[default]
     Infinity,
     OneHour,
     TwoHours,
     Manual,
+    WhileProcess,
 }
 
-impl TimerSelection {
-    pub fn label(&self) -> &'static str {
+impl SelectionKind {
+    /// Stable identifier used on D-Bus, matching the serialized form.
+    pub fn name(&self) -> &'static str {
         match self {
-            TimerSelection::Infinity => "Infinity",
-            TimerSelection::OneHour => "1 Hour",
-            TimerSelection::TwoHours => "2 Hours",
-            TimerSelection::Manual => "Manual",
+            SelectionKind::Infinity => "Infinity",
+            SelectionKind::OneHour => "OneHour",
+            SelectionKind::TwoHours => "TwoHours",
+            SelectionKind::Manual => "Manual",
+            SelectionKind::WhileProcess => "WhileProcess",
+        }
+    }
+}
+
+impl From<TimerSelection> for SelectionKind {
+    fn from(selection: TimerSelection) -> Self {
+        match selection {
+            TimerSelection::Infinity => SelectionKind::Infinity,
+            TimerSelection::Timer(3600) => SelectionKind::OneHour,
+            TimerSelection::Timer(7200) => SelectionKind::TwoHours,
+            TimerSelection::Timer(_) | TimerSelection::Until(_) => SelectionKind::Manual,
+            TimerSelection::WhileProcess => SelectionKind::WhileProcess,
         }
     }
+}
+
+/// A timer offered by name in the popup, over D-Bus and on the command line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimerPreset {
+    pub name: String,
+    pub minutes: u32,
+}
+
+impl TimerPreset {
+    pub fn new(name: &str, minutes: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            minutes,
+        }
+    }
+
+    pub fn secs(&self) -> u64 {
+        self.minutes as u64 * 60
+    }
 
-    pub fn duration_secs(&self, manual_mins: Option<u64>) -> Option<u64> {
+    /// The preset in `presets` called `name`, ignoring case.
+    pub fn find<'a>(presets: &'a [TimerPreset], name: &str) -> Option<&'a TimerPreset> {
+        let name = name.trim();
+        presets
+            .iter()
+            .find(|preset| preset.name.trim().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Whether the screen may still blank and lock while caffeine is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Type)]
+pub enum InhibitMode {
+    /// Keep the screen on.
+    #[default]
+    Full,
+    /// Only keep the machine from suspending; the screen locks as usual.
+    AllowLock,
+}
+
+impl InhibitMode {
+    /// Stable identifier used on D-Bus, matching the serialized form.
+    pub fn name(&self) -> &'static str {
         match self {
-            TimerSelection::Infinity => None,
-            TimerSelection::OneHour => Some(3600),
-            TimerSelection::TwoHours => Some(7200),
-            TimerSelection::Manual => manual_mins.map(|m| m * 60),
+            InhibitMode::Full => "Full",
+            InhibitMode::AllowLock => "AllowLock",
         }
     }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Full" => Some(InhibitMode::Full),
+            "AllowLock" => Some(InhibitMode::AllowLock),
+            _ => None,
+        }
+    }
+}
+
+/// What a running timer does while the machine is suspended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SuspendPolicy {
+    /// Suspended time counts, so the session still ends at `expiry_ts`.
+    #[default]
+    Count,
+    /// The countdown stops while suspended and `expiry_ts` moves later.
+    Pause,
+}
+
+/// What the inhibit keeps from happening, as in the portal's `Inhibit` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, Value, OwnedValue)]
+pub struct InhibitScope {
+    pub idle: bool,
+    pub suspend: bool,
+    pub logout: bool,
+    pub user_switch: bool,
+}
+
+impl Default for InhibitScope {
+    fn default() -> Self {
+        Self {
+            idle: true,
+            suspend: false,
+            logout: false,
+            user_switch: false,
+        }
+    }
+}
+
+impl InhibitScope {
+    pub fn is_empty(&self) -> bool {
+        !(self.idle || self.suspend || self.logout || self.user_switch)
+    }
+
+    /// `AllowLock` when the screen is left to blank and lock.
+    pub fn mode(&self) -> InhibitMode {
+        if self.idle {
+            InhibitMode::Full
+        } else {
+            InhibitMode::AllowLock
+        }
+    }
+
+    /// This scope switched to `mode`, keeping the logout and user switch
+    /// choices. `AllowLock` always blocks suspend, since that is its point.
+    pub fn with_mode(self, mode: InhibitMode) -> Self {
+        match mode {
+            InhibitMode::Full => Self { idle: true, ..self },
+            InhibitMode::AllowLock => Self {
+                idle: false,
+                suspend: true,
+                ..self
+            },
+        }
+    }
+
+    /// The bitmask used by the portal and `org.gnome.SessionManager`.
+    pub fn bits(&self) -> u32 {
+        let mut bits = 0;
+        if self.logout {
+            bits |= 1;
+        }
+        if self.user_switch {
+            bits |= 2;
+        }
+        if self.suspend {
+            bits |= 4;
+        }
+        if self.idle {
+            bits |= 8;
+        }
+        bits
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Type)]
 pub struct CaffeineState {
     pub active: bool,
-    pub selection: TimerSelection,
+    pub selection: SelectionKind,
     pub expiry_ts: i64, // -1 for None, else timestamp
 }
 
 impl CaffeineState {
+    pub fn inactive() -> Self {
+        Session::inactive().state()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+/// The user's session as the service keeps it. Clients see it as a
+/// `CaffeineState` plus the `SelectionName` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    pub active: bool,
+    pub selection: TimerSelection,
+    pub expiry_ts: i64, // -1 for None, else timestamp
+}
+
+impl Session {
     pub fn inactive() -> Self {
         Self {
             active: false,
@@ -59,23 +298,44 @@ impl CaffeineState {
         self.active
     }
 
-    pub fn remaining_secs(&self) -> Option<u64> {
-        if !self.active || self.expiry_ts == -1 {
-            return None;
-        }
-        let ts = self.expiry_ts as u64;
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or(std::time::Duration::from_secs(0))
-            .as_secs();
-        if ts > now {
-            Some(ts - now)
-        } else {
-            Some(0)
+    /// The session as `GetState` and `StateChanged` send it.
+    pub fn state(&self) -> CaffeineState {
+        CaffeineState {
+            active: self.active,
+            selection: self.selection.into(),
+            expiry_ts: self.expiry_ts,
         }
     }
 }
 
+/// How the service treats the user's sessions, set through the config.
+#[derive(Debug, Clone, Default)]
+pub struct SessionConfig {
+    /// What a running timer does while the machine is suspended.
+    pub suspend_policy: SuspendPolicy,
+    /// Whether infinity mode comes back after logging in again.
+    pub restore_infinity: bool,
+    /// What the inhibit covers until it is changed.
+    pub scope: InhibitScope,
+    /// The timers that `EnablePreset` starts by name.
+    pub presets: Vec<TimerPreset>,
+    /// Processes that keep the session awake while any of them runs.
+    pub trigger_processes: Vec<String>,
+}
+
+/// Render a duration the way the popup status line shows it, e.g. `1h 5m`.
+pub fn format_duration(secs: u64) -> String {
+    let hours = secs / 3600;
+    let mins = (secs % 3600) / 60;
+    if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m", mins)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,19 +343,64 @@ mod tests {
     #[test]
     fn timer_selection_label_returns_correct_labels() {
         assert_eq!(TimerSelection::Infinity.label(), "Infinity");
-        assert_eq!(TimerSelection::OneHour.label(), "1 Hour");
-        assert_eq!(TimerSelection::TwoHours.label(), "2 Hours");
-        assert_eq!(TimerSelection::Manual.label(), "Manual");
+        assert_eq!(TimerSelection::Timer(3600).label(), "1h 0m Timer");
+        assert_eq!(TimerSelection::Timer(900).label(), "15m Timer");
+        let evening = TimeOfDay::new(17, 30).unwrap();
+        assert_eq!(TimerSelection::Until(evening).label(), "Until 17:30");
+        assert_eq!(TimerSelection::WhileProcess.label(), "While Process");
     }
 
     #[test]
-    fn timer_selection_duration_secs() {
-        assert_eq!(TimerSelection::Infinity.duration_secs(None), None);
-        assert_eq!(TimerSelection::OneHour.duration_secs(None), Some(3600));
-        assert_eq!(TimerSelection::TwoHours.duration_secs(None), Some(7200));
-        assert_eq!(TimerSelection::Manual.duration_secs(None), None);
-        assert_eq!(TimerSelection::Manual.duration_secs(Some(30)), Some(1800));
-        assert_eq!(TimerSelection::Manual.duration_secs(Some(60)), Some(3600));
+    fn timer_as_long_as_a_preset_is_labelled_by_its_name() {
+        let presets = [("Stand-up", 900), ("1 Hour", 3600)];
+        assert_eq!(TimerSelection::Timer(3600).label_among(presets), "1 Hour");
+        assert_eq!(
+            TimerSelection::Timer(1800).label_among(presets),
+            "30m Timer"
+        );
+        assert_eq!(TimerSelection::Infinity.label_among(presets), "Infinity");
+    }
+
+    #[test]
+    fn timer_selection_name_matches_serialized_form() {
+        for selection in [
+            TimerSelection::Infinity,
+            TimerSelection::Timer(5400),
+            TimerSelection::Until(TimeOfDay::new(8, 0).unwrap()),
+            TimerSelection::WhileProcess,
+        ] {
+            let json = serde_json::to_string(&selection).unwrap();
+            assert_eq!(json, format!("\"{}\"", selection.name()));
+            assert_eq!(
+                TimerSelection::from_name(&selection.name()),
+                Some(selection)
+            );
+        }
+    }
+
+    #[test]
+    fn timer_selection_reads_old_names() {
+        let old: TimerSelection = serde_json::from_str("\"TwoHours\"").unwrap();
+        assert_eq!(old, TimerSelection::Timer(7200));
+        assert_eq!(
+            TimerSelection::from_name("OneHour"),
+            Some(TimerSelection::Timer(3600))
+        );
+        assert_eq!(TimerSelection::from_name("Manual"), None);
+        assert_eq!(TimerSelection::from_name("Timer:soon"), None);
+        assert_eq!(TimerSelection::from_name("Until:25:00"), None);
+    }
+
+    #[test]
+    fn timer_preset_found_by_name_ignoring_case() {
+        let presets = [
+            TimerPreset::new("Stand-up", 15),
+            TimerPreset::new("Movie", 150),
+        ];
+        assert_eq!(TimerPreset::find(&presets, "movie"), Some(&presets[1]));
+        assert_eq!(TimerPreset::find(&presets, " Stand-up "), Some(&presets[0]));
+        assert_eq!(TimerPreset::find(&presets, "Deploy"), None);
+        assert_eq!(presets[1].secs(), 9000);
     }
 
     #[test]
@@ -103,74 +408,114 @@ mod tests {
         let state = CaffeineState::inactive();
         assert!(!state.is_active());
         assert_eq!(state.expiry_ts, -1);
-        assert_eq!(state.selection, TimerSelection::default());
+        assert_eq!(state.selection, SelectionKind::default());
     }
 
     #[test]
     fn caffeine_state_active_with_expiry() {
         let expiry = 1704067200; // Some fixed timestamp
-        let state = CaffeineState::active(TimerSelection::OneHour, Some(expiry));
+        let state = Session::active(TimerSelection::Timer(3600), Some(expiry)).state();
         assert!(state.is_active());
         assert_eq!(state.expiry_ts, expiry as i64);
-        assert_eq!(state.selection, TimerSelection::OneHour);
+        assert_eq!(state.selection, SelectionKind::OneHour);
     }
 
     #[test]
     fn caffeine_state_active_without_expiry() {
-        let state = CaffeineState::active(TimerSelection::Infinity, None);
+        let state = Session::active(TimerSelection::Infinity, None).state();
         assert!(state.is_active());
         assert_eq!(state.expiry_ts, -1);
-        assert_eq!(state.selection, TimerSelection::Infinity);
+        assert_eq!(state.selection, SelectionKind::Infinity);
     }
 
     #[test]
-    fn caffeine_state_remaining_secs_inactive() {
-        let state = CaffeineState::inactive();
-        assert_eq!(state.remaining_secs(), None);
+    fn caffeine_state_keeps_its_wire_format() {
+        assert_eq!(CaffeineState::SIGNATURE.to_string(), "(bux)");
+        let kind = |selection| SelectionKind::from(selection);
+        assert_eq!(kind(TimerSelection::Timer(7200)), SelectionKind::TwoHours);
+        assert_eq!(kind(TimerSelection::Timer(5400)), SelectionKind::Manual);
+        let evening = TimeOfDay::new(17, 30).unwrap();
+        assert_eq!(kind(TimerSelection::Until(evening)), SelectionKind::Manual);
+        assert_eq!(SelectionKind::Manual.name(), "Manual");
     }
 
     #[test]
-    fn caffeine_state_remaining_secs_infinity() {
-        let state = CaffeineState::active(TimerSelection::Infinity, None);
-        assert_eq!(state.remaining_secs(), None);
+    fn timer_selection_default_is_infinity() {
+        assert_eq!(TimerSelection::default(), TimerSelection::Infinity);
     }
 
     #[test]
-    fn caffeine_state_remaining_secs_future() {
-        let future_ts = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            + 3600;
-        let state = CaffeineState::active(TimerSelection::OneHour, Some(future_ts));
-        let remaining = state.remaining_secs();
-        assert!(remaining.is_some());
-        assert!(remaining.unwrap() > 3500 && remaining.unwrap() <= 3600);
+    fn caffeine_state_equality() {
+        let state1 = CaffeineState::inactive();
+        let state2 = CaffeineState::inactive();
+        assert_eq!(state1, state2);
+
+        let state3 = Session::active(TimerSelection::Timer(3600), Some(1000)).state();
+        let state4 = Session::active(TimerSelection::Timer(3600), Some(1000)).state();
+        assert_eq!(state3, state4);
+
+        let state5 = Session::active(TimerSelection::Timer(7200), Some(1000)).state();
+        assert_ne!(state3, state5);
     }
 
     #[test]
-    fn caffeine_state_remaining_secs_expired() {
-        let past_ts = 1; // Unix epoch - definitely expired
-        let state = CaffeineState::active(TimerSelection::OneHour, Some(past_ts));
-        assert_eq!(state.remaining_secs(), Some(0));
+    fn inhibit_scope_defaults_to_idle() {
+        let scope = InhibitScope::default();
+        assert!(scope.idle);
+        assert!(!scope.is_empty());
+        assert_eq!(scope.bits(), 8);
     }
 
     #[test]
-    fn timer_selection_default_is_infinity() {
-        assert_eq!(TimerSelection::default(), TimerSelection::Infinity);
+    fn inhibit_scope_bits_match_portal_flags() {
+        let scope = InhibitScope {
+            idle: false,
+            suspend: true,
+            logout: true,
+            user_switch: false,
+        };
+        assert_eq!(scope.bits(), 1 | 4);
+
+        let empty = InhibitScope {
+            suspend: false,
+            logout: false,
+            ..scope
+        };
+        assert!(empty.is_empty());
+        assert_eq!(empty.bits(), 0);
     }
 
     #[test]
-    fn caffeine_state_equality() {
-        let state1 = CaffeineState::inactive();
-        let state2 = CaffeineState::inactive();
-        assert_eq!(state1, state2);
+    fn inhibit_mode_round_trips_through_scope() {
+        let scope = InhibitScope::default();
+        assert_eq!(scope.mode(), InhibitMode::Full);
 
-        let state3 = CaffeineState::active(TimerSelection::OneHour, Some(1000));
-        let state4 = CaffeineState::active(TimerSelection::OneHour, Some(1000));
-        assert_eq!(state3, state4);
+        let allow_lock = scope.with_mode(InhibitMode::AllowLock);
+        assert!(!allow_lock.idle);
+        assert!(allow_lock.suspend);
+        assert_eq!(allow_lock.mode(), InhibitMode::AllowLock);
 
-        let state5 = CaffeineState::active(TimerSelection::TwoHours, Some(1000));
-        assert_ne!(state3, state5);
+        let full = allow_lock.with_mode(InhibitMode::Full);
+        assert!(full.idle);
+        assert!(full.suspend);
+        assert_eq!(full.mode(), InhibitMode::Full);
+    }
+
+    #[test]
+    fn inhibit_mode_name_matches_serialized_form() {
+        for mode in [InhibitMode::Full, InhibitMode::AllowLock] {
+            let json = serde_json::to_string(&mode).unwrap();
+            assert_eq!(json, format!("\"{}\"", mode.name()));
+            assert_eq!(InhibitMode::from_name(mode.name()), Some(mode));
+        }
+        assert_eq!(InhibitMode::from_name("Bogus"), None);
+    }
+
+    #[test]
+    fn format_duration_picks_largest_units() {
+        assert_eq!(format_duration(5400), "1h 30m");
+        assert_eq!(format_duration(7200), "2h 0m");
+        assert_eq!(format_duration(300), "5m");
+        assert_eq!(format_duration(42), "42s");
     }
 }
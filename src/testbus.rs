@@ -0,0 +1,495 @@
+//! A private session bus for tests, with stand-ins for the inhibit portal
+//! and logind.
+//!
+//! Every test using it shares one `dbus-daemon` started on first use, so
+//! nothing touches the desktop session running the tests. ashpd and the
+//! backends find the session bus through `DBUS_SESSION_BUS_ADDRESS`, so each
+//! test runs again in a child process started with it pointing at that bus,
+//! rather than changing the environment of the test binary.
+
+use futures_util::FutureExt;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::panic::AssertUnwindSafe;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{interface, Connection, ObjectServer};
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const LOGIND_NAME: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+
+/// How long to wait for something the service does in the background.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A session bus with no activatable services, so a missing portal stays
+/// missing.
+const BUS_CONFIG: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:tmpdir=/tmp</listen>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#;
+
+/// Set in the child process a test runs in, to the address of the bus.
+const BUS_ADDRESS: &str = "COSMIC_CAFFEINE_TEST_BUS";
+
+/// Tests share the bus names, so they run one at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Connections opened by the running test, closed when it ends.
+static OPEN: Mutex<Vec<Connection>> = Mutex::new(Vec::new());
+
+/// Stand-ins started by the running test. They are closed after everything
+/// else, or a service from the test could see its portal leave and inhibit
+/// again, through ashpd's connection, on the portal of the next test.
+static STAND_INS: Mutex<Vec<Connection>> = Mutex::new(Vec::new());
+
+/// Start `dbus-daemon` and return its address. It is killed when the test
+/// binary exits.
+fn start_bus() -> String {
+    let config = std::env::temp_dir().join(format!(
+        "cosmic-caffeine-test-bus-{}.conf",
+        std::process::id()
+    ));
+    std::fs::write(&config, BUS_CONFIG).expect("failed to write bus config");
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    // `PR_SET_PDEATHSIG` fires when the spawning thread exits, so spawn from
+    // a thread that lives as long as the process.
+    std::thread::spawn(move || {
+        let mut command = Command::new("dbus-daemon");
+        command
+            .arg("--nofork")
+            .arg("--print-address=1")
+            .arg(format!("--config-file={}", config.display()))
+            .stdout(Stdio::piped());
+        // SAFETY: the closure runs between fork and exec, where only
+        // async-signal-safe calls are allowed, and it only calls `prctl`.
+        unsafe {
+            command.pre_exec(|| {
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+                Ok(())
+            });
+        }
+
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = tx.send(Err(e));
+                return;
+            }
+        };
+        let mut address = String::new();
+        let read = child
+            .stdout
+            .take()
+            .map(|stdout| BufReader::new(stdout).read_line(&mut address));
+        let _ = std::fs::remove_file(&config);
+        let _ = tx.send(match read {
+            Some(Ok(_)) => Ok(address.trim().to_string()),
+            Some(Err(e)) => Err(e),
+            None => Err(std::io::Error::other("no stdout")),
+        });
+
+        let _ = child.wait();
+    });
+
+    rx.recv()
+        .expect("bus thread exited")
+        .unwrap_or_else(|e| panic!("failed to start dbus-daemon, is it installed? {}", e))
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build runtime")
+    })
+}
+
+/// Run `test` on the private bus, closing every connection it opened with
+/// `connect` afterwards, even if it panicked.
+///
+/// Called from a test, this runs the same test again in a child process on
+/// the bus and fails if it does; in that child it runs `test` itself.
+pub fn run(test: impl Future<Output = ()>) {
+    if std::env::var_os(BUS_ADDRESS).is_some() {
+        run_here(test);
+    } else {
+        run_in_child();
+    }
+}
+
+fn run_in_child() {
+    static ADDRESS: OnceLock<String> = OnceLock::new();
+
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let address = ADDRESS.get_or_init(start_bus);
+
+    // libtest runs each test on a thread named after it.
+    let name = std::thread::current()
+        .name()
+        .expect("tests run on named threads")
+        .to_string();
+    let output = Command::new(std::env::current_exe().expect("no test binary"))
+        .args([name.as_str(), "--exact", "--test-threads=1", "--nocapture"])
+        .env(BUS_ADDRESS, address)
+        .env("DBUS_SESSION_BUS_ADDRESS", address)
+        .output()
+        .expect("failed to run the test in a child process");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success() && stdout.contains("1 passed"),
+        "{} failed on the test bus:\n{}{}",
+        name,
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn run_here(test: impl Future<Output = ()>) {
+    let outcome = runtime().block_on(async {
+        let outcome = AssertUnwindSafe(test).catch_unwind().await;
+        close_all(&OPEN).await;
+        close_all(&STAND_INS).await;
+        outcome
+    });
+    if let Err(panic) = outcome {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+/// Close the connections in `open` and wait for the bus to drop them.
+async fn close_all(open: &Mutex<Vec<Connection>>) {
+    let open = std::mem::take(&mut *open.lock().unwrap_or_else(|e| e.into_inner()));
+    let Ok(observer) = session().await else {
+        return;
+    };
+    let Ok(dbus) = zbus::fdo::DBusProxy::new(&observer).await else {
+        return;
+    };
+
+    for conn in open {
+        let name = conn.unique_name().cloned();
+        let _ = conn.close().await;
+        let Some(name) = name else {
+            continue;
+        };
+        let gone = async {
+            while dbus
+                .name_has_owner(name.as_ref().into())
+                .await
+                .unwrap_or(false)
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let _ = tokio::time::timeout(TIMEOUT, gone).await;
+    }
+}
+
+/// Connect to the bus this child process was started for.
+async fn session() -> zbus::Result<Connection> {
+    let address = std::env::var(BUS_ADDRESS).map_err(|e| zbus::Error::Address(e.to_string()))?;
+    zbus::connection::Builder::address(address.as_str())?
+        .build()
+        .await
+}
+
+async fn open(list: &Mutex<Vec<Connection>>) -> Connection {
+    let conn = session().await.expect("failed to connect to the test bus");
+    list.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(conn.clone());
+    conn
+}
+
+/// Open a new connection to the private bus.
+pub async fn connect() -> Connection {
+    open(&OPEN).await
+}
+
+/// A call made to the stand-in portal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortalCall {
+    Inhibit { flags: u32, reason: String },
+    Close,
+}
+
+type Calls = Arc<Mutex<Vec<PortalCall>>>;
+
+fn record(calls: &Calls, call: PortalCall) {
+    calls.lock().unwrap_or_else(|e| e.into_inner()).push(call);
+}
+
+/// A stand-in for `org.freedesktop.portal.Inhibit` that records its calls.
+pub struct MockPortal {
+    calls: Calls,
+    conn: Connection,
+}
+
+impl MockPortal {
+    /// Claim the portal name and grant every inhibit request.
+    pub async fn start() -> Self {
+        Self::start_with(false).await
+    }
+
+    /// Claim the portal name and refuse every inhibit request.
+    pub async fn denying() -> Self {
+        Self::start_with(true).await
+    }
+
+    async fn start_with(deny: bool) -> Self {
+        let calls = Calls::default();
+        let conn = open(&STAND_INS).await;
+        let inhibit = InhibitPortal {
+            calls: calls.clone(),
+            deny,
+        };
+        conn.object_server()
+            .at(PORTAL_PATH, inhibit)
+            .await
+            .expect("failed to export portal");
+        conn.request_name(PORTAL_NAME)
+            .await
+            .expect("failed to claim portal name");
+        Self { calls, conn }
+    }
+
+    pub fn calls(&self) -> Vec<PortalCall> {
+        self.calls.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Wait until at least `count` calls have arrived, for calls the
+    /// service makes in the background.
+    pub async fn wait_for_calls(&self, count: usize) -> Vec<PortalCall> {
+        let arrived = async {
+            loop {
+                let calls = self.calls();
+                if calls.len() >= count {
+                    return calls;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(TIMEOUT, arrived)
+            .await
+            .unwrap_or_else(|_| panic!("expected {} portal calls, got {:?}", count, self.calls()))
+    }
+
+    /// Leave the bus, as a crashing portal would.
+    pub async fn stop(self) {
+        let _ = self.conn.close().await;
+    }
+}
+
+/// Errors a real portal replies with.
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.freedesktop.portal.Error")]
+enum PortalError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    NotAllowed(String),
+}
+
+struct InhibitPortal {
+    calls: Calls,
+    deny: bool,
+}
+
+#[interface(name = "org.freedesktop.portal.Inhibit")]
+impl InhibitPortal {
+    async fn inhibit(
+        &self,
+        _window: String,
+        flags: u32,
+        options: HashMap<String, OwnedValue>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] conn: &Connection,
+    ) -> Result<OwnedObjectPath, PortalError> {
+        let option = |key: &str| {
+            options
+                .get(key)
+                .and_then(|value| value.try_clone().ok())
+                .and_then(|value| String::try_from(value).ok())
+                .unwrap_or_default()
+        };
+        record(
+            &self.calls,
+            PortalCall::Inhibit {
+                flags,
+                reason: option("reason"),
+            },
+        );
+        if self.deny {
+            return Err(PortalError::NotAllowed(
+                "Inhibiting is not allowed".to_string(),
+            ));
+        }
+
+        // The handle the caller expects, see the `Request` documentation.
+        let sender = header
+            .sender()
+            .map(|sender| sender.trim_start_matches(':').replace('.', "_"))
+            .unwrap_or_default();
+        let path = format!(
+            "{}/request/{}/{}",
+            PORTAL_PATH,
+            sender,
+            option("handle_token")
+        );
+        let path = OwnedObjectPath::try_from(path).map_err(zbus::Error::from)?;
+        let request = PortalRequest {
+            calls: self.calls.clone(),
+        };
+        conn.object_server().at(&path, request).await?;
+
+        // The response follows the reply, as with a real portal.
+        let emitter = SignalEmitter::new(conn, path.clone())?.into_owned();
+        tokio::spawn(async move {
+            let _ = PortalRequest::response(&emitter, 0, HashMap::new()).await;
+        });
+        Ok(path)
+    }
+
+    #[zbus(property, name = "version")]
+    fn version(&self) -> u32 {
+        3
+    }
+}
+
+struct PortalRequest {
+    calls: Calls,
+}
+
+#[interface(name = "org.freedesktop.portal.Request")]
+impl PortalRequest {
+    async fn close(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) {
+        record(&self.calls, PortalCall::Close);
+        if let Some(path) = header.path() {
+            let _ = server.remove::<PortalRequest, _>(path.to_owned()).await;
+        }
+    }
+
+    #[zbus(signal)]
+    async fn response(
+        emitter: &SignalEmitter<'_>,
+        response: u32,
+        results: HashMap<&str, OwnedValue>,
+    ) -> zbus::Result<()>;
+}
+
+/// Locks handed out by the stand-in logind: what each covers, and our end
+/// of a socket whose other end the caller holds as the lock's fd.
+type Locks = Arc<Mutex<Vec<(String, UnixStream)>>>;
+
+/// A stand-in for logind that announces suspend and resume and hands out
+/// inhibitor locks.
+pub struct MockLogind {
+    conn: Connection,
+    locks: Locks,
+}
+
+impl MockLogind {
+    /// Claim the logind name.
+    pub async fn start() -> Self {
+        let locks = Locks::default();
+        let conn = open(&STAND_INS).await;
+        let manager = LogindManager {
+            locks: locks.clone(),
+        };
+        conn.object_server()
+            .at(LOGIND_PATH, manager)
+            .await
+            .expect("failed to export logind");
+        conn.request_name(LOGIND_NAME)
+            .await
+            .expect("failed to claim logind name");
+        Self { conn, locks }
+    }
+
+    /// What the locks still held cover, e.g. `sleep`, in the order taken.
+    /// A lock is held until every copy of its fd is closed.
+    pub fn held_locks(&self) -> Vec<String> {
+        self.locks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|(_, peer)| is_open(peer))
+            .map(|(what, _)| what.clone())
+            .collect()
+    }
+
+    /// Announce that the machine is about to suspend.
+    pub async fn suspend(&self) {
+        self.prepare_for_sleep(true).await;
+    }
+
+    /// Announce that the machine has resumed.
+    pub async fn resume(&self) {
+        self.prepare_for_sleep(false).await;
+    }
+
+    async fn prepare_for_sleep(&self, start: bool) {
+        let emitter = SignalEmitter::new(&self.conn, LOGIND_PATH).unwrap();
+        LogindManager::prepare_for_sleep(&emitter, start)
+            .await
+            .expect("failed to emit PrepareForSleep");
+    }
+}
+
+/// Whether the other end of `peer` is still open. Nothing is ever written
+/// to it, so a read only returns once that end is closed.
+fn is_open(mut peer: &UnixStream) -> bool {
+    let mut byte = [0; 1];
+    matches!(peer.read(&mut byte), Err(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+struct LogindManager {
+    locks: Locks,
+}
+
+#[interface(name = "org.freedesktop.login1.Manager")]
+impl LogindManager {
+    fn inhibit(
+        &self,
+        what: String,
+        _who: String,
+        _why: String,
+        _mode: String,
+    ) -> zbus::fdo::Result<zbus::zvariant::OwnedFd> {
+        let (fd, peer) = UnixStream::pair().map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+        peer.set_nonblocking(true)
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+        self.locks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((what, peer));
+        Ok(std::os::fd::OwnedFd::from(fd).into())
+    }
+
+    #[zbus(signal)]
+    async fn prepare_for_sleep(emitter: &SignalEmitter<'_>, start: bool) -> zbus::Result<()>;
+}
@@ -0,0 +1,107 @@
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use tokio::process::{Child, Command};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+use crate::backend::{BackendConfig, CaffeineBackend};
+
+/// Run `argv` with an idle inhibit held for as long as it is alive.
+///
+/// Returns the exit code to propagate: the child's own code, `128 + signal`
+/// if it was killed, or 126/127 if it could not be started, like a shell.
+pub fn run(argv: &[String], backend: BackendConfig) -> i32 {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("cosmic-caffeine: {}", e);
+            return 1;
+        }
+    };
+
+    runtime.block_on(run_inhibited(argv, backend))
+}
+
+async fn run_inhibited(argv: &[String], backend: BackendConfig) -> i32 {
+    let Some((program, args)) = argv.split_first() else {
+        eprintln!("cosmic-caffeine: no command given");
+        return 2;
+    };
+
+    let backend = CaffeineBackend::new(backend);
+    let reason = format!("Running {}", argv.join(" "));
+    if let Err(e) = backend.inhibit(&reason).await {
+        warn!("Could not inhibit idle, running {} anyway: {}", program, e);
+    }
+
+    let code = match Command::new(program).args(args).spawn() {
+        Ok(mut child) => match wait_forwarding_signals(&mut child).await {
+            Ok(status) => {
+                info!("{} exited with {}", program, status);
+                exit_code(status)
+            }
+            Err(e) => {
+                eprintln!("cosmic-caffeine: failed to wait for {}: {}", program, e);
+                1
+            }
+        },
+        Err(e) => {
+            eprintln!("cosmic-caffeine: failed to run {}: {}", program, e);
+            if e.kind() == io::ErrorKind::NotFound {
+                127
+            } else {
+                126
+            }
+        }
+    };
+
+    if let Err(e) = backend.uninhibit().await {
+        warn!("Failed to release inhibit: {}", e);
+    }
+
+    code
+}
+
+/// Wait for `child`, passing on signals that were meant for it.
+///
+/// SIGINT and SIGQUIT from a terminal already reach the child through the
+/// foreground process group, so they are only caught to keep us alive long
+/// enough to release the inhibit once the child is gone.
+async fn wait_forwarding_signals(child: &mut Child) -> io::Result<ExitStatus> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    let mut sigusr1 = signal(SignalKind::user_defined1())?;
+    let mut sigusr2 = signal(SignalKind::user_defined2())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigquit = signal(SignalKind::quit())?;
+
+    loop {
+        let forward = tokio::select! {
+            status = child.wait() => return status,
+            _ = sigterm.recv() => libc::SIGTERM,
+            _ = sighup.recv() => libc::SIGHUP,
+            _ = sigusr1.recv() => libc::SIGUSR1,
+            _ = sigusr2.recv() => libc::SIGUSR2,
+            _ = sigint.recv() => continue,
+            _ = sigquit.recv() => continue,
+        };
+
+        if let Some(pid) = child.id() {
+            // SAFETY: kill(2) has no memory-safety requirements; the pid
+            // belongs to a child we have not reaped yet.
+            unsafe {
+                libc::kill(pid as libc::pid_t, forward);
+            }
+        }
+    }
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}